@@ -60,6 +60,33 @@ impl AABB {
         )
     }
 
+    pub fn pad_to_minimums(&self) -> Self {
+        // Adjust the AABB so that no side is narrower than some delta, padding if necessary.
+        // Planar primitives such as quads would otherwise produce a degenerate box
+        let delta = 0.0001;
+        let pad = |i: &Interval| {
+            if i.size() < delta {
+                i.expand(delta)
+            } else {
+                *i
+            }
+        };
+        Self::new(pad(&self.x), pad(&self.y), pad(&self.z))
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            (self.x.min + self.x.max) * 0.5,
+            (self.y.min + self.y.max) * 0.5,
+            (self.z.min + self.z.max) * 0.5,
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        2. * (dx * dy + dy * dz + dz * dx)
+    }
+
     pub fn longest_axis(&self) -> i32 {
         if self.x.size() > self.y.size() {
             if self.x.size() > self.z.size() {
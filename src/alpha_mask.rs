@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    ray::{Point3, Ray},
+    texture::Texture,
+    vec3::Vec3,
+};
+
+/// Cuts holes into an object where the alpha of a mask texture falls below a threshold, such
+/// as the transparent parts of a leaf or a decal. Rays pass through the holes as if nothing was
+/// there and hit whatever lies behind, shadow rays included.
+#[derive(Debug)]
+pub struct AlphaMasked {
+    object: Arc<dyn Hittable>,
+    mask: Arc<dyn Texture>,
+    threshold: f64,
+}
+
+impl AlphaMasked {
+    pub fn new(object: Arc<dyn Hittable>, mask: Arc<dyn Texture>) -> Self {
+        Self::with_threshold(object, mask, 0.5)
+    }
+
+    pub fn with_threshold(
+        object: Arc<dyn Hittable>,
+        mask: Arc<dyn Texture>,
+        threshold: f64,
+    ) -> Self {
+        Self {
+            object,
+            mask,
+            threshold,
+        }
+    }
+}
+
+impl Hittable for AlphaMasked {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Keep looking past cut out hits until one is solid or the object runs out, a curved
+        // or composite object can be hit several times along the ray. Hits go into a record of
+        // our own, since callers such as the BVH keep using rec after a miss
+        let mut temp_rec = HitRecord::default();
+        let mut t_min = ray_t.min;
+        while self
+            .object
+            .hit(r, Interval::new(t_min, ray_t.max), &mut temp_rec)
+        {
+            if self.mask.alpha(temp_rec.u, temp_rec.v, &temp_rec.p) >= self.threshold {
+                *rec = temp_rec;
+                return true;
+            }
+            t_min = temp_rec.t.next_up();
+        }
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.object.bounding_box()
+    }
+
+    // As a light the object is sampled whole, holes included. Directions towards a hole just
+    // see what lies behind it, and points that land in one are dropped, which leaves the
+    // estimates unbiased since the holes give off no light
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.object.random(origin)
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        let (rec, pdf) = self.object.sample_surface()?;
+        (self.mask.alpha(rec.u, rec.v, &rec.p) >= self.threshold).then_some((rec, pdf))
+    }
+
+    fn surface_pdf(&self, origin: &Point3, point: &Point3) -> f64 {
+        self.object.surface_pdf(origin, point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bvh::BVHNode,
+        color::Color,
+        hittable::HittableList,
+        linear_bvh::LinearBVH,
+        material::Lambertian,
+        quad::Quad,
+        texture::{ColorSpace, ImageTexture, SolidColor},
+    };
+
+    // Opaque on the left half of the uv square, see-through on the right
+    #[derive(Debug)]
+    struct HalfMask;
+
+    impl Texture for HalfMask {
+        fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+            Color::new(1., 1., 1.)
+        }
+
+        fn alpha(&self, u: f64, _v: f64, _p: &Point3) -> f64 {
+            if u < 0.5 {
+                1.
+            } else {
+                0.
+            }
+        }
+    }
+
+    fn unit_quad(z: f64) -> Arc<dyn Hittable> {
+        Arc::new(Quad::new(
+            Point3::new(0., 0., z),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    // A half masked quad in front of a solid one
+    fn scene() -> HittableList {
+        let mut world = HittableList::new();
+        world.add(Arc::new(AlphaMasked::new(
+            unit_quad(0.),
+            Arc::new(HalfMask),
+        )));
+        world.add(unit_quad(-1.));
+        world
+    }
+
+    // Where a ray straight down the z axis at (x, 0.5) hits
+    fn hit_z(world: &dyn Hittable, x: f64) -> (f64, f64) {
+        let r = Ray::new(Point3::new(x, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        (rec.p.z(), rec.t)
+    }
+
+    #[test]
+    fn rays_pass_through_holes() {
+        let world = scene();
+        assert_eq!(hit_z(&world, 0.25), (0., 1.));
+        assert_eq!(hit_z(&world, 0.75), (-1., 2.));
+    }
+
+    #[test]
+    fn holes_leave_the_record_alone() {
+        // Acceleration structures reuse the record between children, so a miss must not leave
+        // the cut out hit behind
+        let bvh = BVHNode::new(&mut scene());
+        assert_eq!(hit_z(bvh.as_ref(), 0.25), (0., 1.));
+        assert_eq!(hit_z(bvh.as_ref(), 0.75), (-1., 2.));
+        let linear = LinearBVH::new(&scene());
+        assert_eq!(hit_z(linear.as_ref(), 0.25), (0., 1.));
+        assert_eq!(hit_z(linear.as_ref(), 0.75), (-1., 2.));
+
+        let masked = AlphaMasked::new(unit_quad(0.), Arc::new(HalfMask));
+        let r = Ray::new(Point3::new(0.75, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(!masked.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert_eq!(rec.t, 0.);
+    }
+
+    #[test]
+    fn opaque_textures_keep_everything() {
+        let masked = AlphaMasked::new(unit_quad(0.), Arc::new(SolidColor::with_rgb(0., 0., 0.)));
+        let r = Ray::new(Point3::new(0.75, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(masked.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+    }
+
+    #[test]
+    fn light_samples_skip_holes() {
+        let masked = AlphaMasked::new(unit_quad(0.), Arc::new(HalfMask));
+        let samples: Vec<_> = (0..200).map(|_| masked.sample_surface()).collect();
+        assert!(samples.iter().any(|s| s.is_none()));
+        for (rec, pdf) in samples.into_iter().flatten() {
+            assert!(rec.u < 0.5);
+            assert_eq!(pdf, 1.);
+        }
+    }
+
+    #[test]
+    fn image_alpha_is_kept() {
+        let path = std::env::temp_dir().join("rrtm_alpha_mask_test.png");
+        let image = image::RgbaImage::from_fn(2, 1, |x, _| {
+            image::Rgba([255, 255, 255, if x == 0 { 255 } else { 0 }])
+        });
+        image.save(&path).unwrap();
+
+        let texture = ImageTexture::open(&path, ColorSpace::Srgb).unwrap();
+        let p = Point3::default();
+        assert_eq!(texture.alpha(0.25, 0.5, &p), 1.);
+        assert_eq!(texture.alpha(0.75, 0.5, &p), 0.);
+        assert_eq!(texture.value(0.75, 0.5, &p), Color::new(1., 1., 1.));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use crate::{
+    color::Color,
+    environment::EnvironmentMap,
+    texture::Texture,
+    vec3::{unit_vector, Vec3},
+};
+
+// What a ray sees when it escapes the scene without hitting anything
+#[derive(Debug, Clone)]
+pub enum Background {
+    Solid(Color),
+    // Vertical blend from the horizon color (straight down) to the zenith color (straight up)
+    Gradient { horizon: Color, zenith: Color },
+    // Texture looked up with latitude-longitude coordinates of the ray direction
+    Texture(Arc<dyn Texture>),
+    // HDR panorama in linear radiance, importance sampled when lighting diffuse surfaces
+    Environment(Arc<EnvironmentMap>),
+}
+
+impl Background {
+    // The default white to light blue sky
+    pub fn sky() -> Self {
+        Background::Gradient {
+            horizon: Color::new(1., 1., 1.),
+            zenith: Color::new(0.5, 0.7, 1.),
+        }
+    }
+
+    pub fn value(&self, direction: &Vec3) -> Color {
+        let unit_direction = unit_vector(direction);
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { horizon, zenith } => {
+                let a = 0.5 * (unit_direction.y() + 1.0);
+                *horizon * (1. - a) + *zenith * a
+            }
+            Background::Texture(tex) => {
+                let (u, v) = direction_to_uv(&unit_direction);
+                tex.value(u, v, &unit_direction)
+            }
+            Background::Environment(env) => env.value(&unit_direction),
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::sky()
+    }
+}
+
+/// Map a unit direction to equirectangular (u, v) coordinates.
+/// u wraps around the Y-axis starting from -Z, v goes from 0 straight down to 1 straight up,
+/// which matches ImageTexture's bottom-up v convention for lat-long panoramas.
+pub fn direction_to_uv(d: &Vec3) -> (f64, f64) {
+    let u = 0.5 + f64::atan2(d.x(), -d.z()) / (2. * PI);
+    let v = 0.5 + f64::asin(d.y().clamp(-1., 1.)) / PI;
+    (u, v)
+}
+
+// Inverse of direction_to_uv
+pub fn uv_to_direction(u: f64, v: f64) -> Vec3 {
+    let phi = (u - 0.5) * 2. * PI;
+    let latitude = (v - 0.5) * PI;
+    let cos_latitude = f64::cos(latitude);
+    Vec3::new(
+        f64::sin(phi) * cos_latitude,
+        f64::sin(latitude),
+        -f64::cos(phi) * cos_latitude,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uv_direction_roundtrip() {
+        for _ in 0..100 {
+            let d = Vec3::random_unit_vector();
+            let (u, v) = direction_to_uv(&d);
+            assert!((0. ..=1.).contains(&u) && (0. ..=1.).contains(&v));
+            assert!((uv_to_direction(u, v) - d).near_zero());
+        }
+    }
+}
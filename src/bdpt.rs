@@ -0,0 +1,604 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use rayon::prelude::*;
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    ray::{Point3, Ray},
+    utils::random_double,
+    vec3::{dot, unit_vector, Vec3},
+};
+
+// Bidirectional path tracing.
+//
+// Every sample traces one subpath from the camera and one from a random point on the camera's
+// lights, then joins every prefix of the one to every prefix of the other with a shadow ray.
+// A path with n vertices can be built in n + 1 such ways (strategies), each good at finding
+// different kinds of light transport: hitting a light by chance, sampling the light from a
+// diffuse surface, or carrying light through glass before connecting it to the camera. The
+// results of all strategies are combined with the balance heuristic, which needs the density
+// of every strategy producing the same path. Those are kept on the vertices as pdf_fwd and
+// pdf_rev, the area densities of sampling a vertex from its neighbour towards the camera and
+// towards the light.
+//
+// Paths that connect straight to the lens (t = 1) can land on any pixel, so their light is
+// splatted onto the whole image instead of the pixel being sampled. The background is only
+// found by camera paths that escape the scene.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Debug, Clone)]
+struct Vertex {
+    kind: VertexKind,
+    rec: HitRecord,
+    r_in: Ray,   // the ray that reached the vertex, to evaluate its material
+    beta: Color, // throughput of the subpath up to, not including, this vertex
+    delta: bool, // only scatters into a single direction, can't be connected to
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn camera(p: Point3, beta: Color) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            rec: HitRecord {
+                p,
+                ..Default::default()
+            },
+            r_in: Ray::default(),
+            beta,
+            delta: false,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+        }
+    }
+
+    fn light(rec: HitRecord, pdf: f64) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            rec,
+            r_in: Ray::default(),
+            beta: Color::new(1., 1., 1.) / pdf,
+            delta: false,
+            pdf_fwd: pdf,
+            pdf_rev: 0.,
+        }
+    }
+
+    fn p(&self) -> Point3 {
+        self.rec.p
+    }
+
+    // Light leaving the vertex towards the given point per unit of incoming light, with the
+    // cosine at this vertex included: the material's eval() for surfaces, and the emitted light
+    // for points on lights
+    fn f(&self, towards: &Point3) -> Color {
+        let direction = *towards - self.p();
+        match self.kind {
+            VertexKind::Camera => Color::default(),
+            VertexKind::Light => {
+                let emitted = match &self.rec.material {
+                    Some(material) => material.emitted(self.rec.u, self.rec.v, &self.rec.p),
+                    None => Color::default(),
+                };
+                emitted * f64::abs(dot(self.rec.normal, unit_vector(&direction)))
+            }
+            VertexKind::Surface => {
+                let scattered = Ray::new_tm(self.p(), direction, self.r_in.time());
+                let material = self.rec.material.as_ref().unwrap();
+                material.eval(&self.r_in, &self.rec, &scattered)
+            }
+        }
+    }
+
+    // Turn a solid angle density of sampling the direction from this vertex to next into an
+    // area density at next
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p() - self.p();
+        let distance_squared = w.length_squared();
+        if pdf <= 0. || distance_squared <= 0. {
+            return 0.;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.kind != VertexKind::Camera {
+            pdf *= f64::abs(dot(next.rec.normal, w)) / distance_squared.sqrt();
+        }
+        pdf
+    }
+
+    // Area density of this vertex sampling next, when it was reached from prev
+    fn pdf(&self, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let direction = next.p() - self.p();
+        let pdf = match self.kind {
+            VertexKind::Camera => camera.direction_pdf(&direction),
+            // Lights emit cosine weighted from both faces
+            VertexKind::Light => {
+                f64::abs(dot(self.rec.normal, unit_vector(&direction))) / (2. * PI)
+            }
+            VertexKind::Surface => {
+                let prev = prev.unwrap();
+                let r_in = Ray::new_tm(prev.p(), self.p() - prev.p(), self.r_in.time());
+                let scattered = Ray::new_tm(self.p(), direction, self.r_in.time());
+                let material = self.rec.material.as_ref().unwrap();
+                material.pdf(&r_in, &self.rec, &scattered)
+            }
+        };
+        self.convert_density(pdf, next)
+    }
+}
+
+pub fn render(camera: &Camera, world: &Arc<dyn Hittable>) -> Vec<Color> {
+    let (width, height) = (camera.image_width(), camera.image_height());
+    let film = (0..height)
+        .into_par_iter()
+        .fold(
+            || vec![Color::default(); width * height],
+            |mut film, j| {
+                for i in 0..width {
+                    for _ in 0..camera.samples_per_pixel {
+                        sample(camera, world, i, j, &mut film);
+                    }
+                }
+                film
+            },
+        )
+        .reduce(
+            || vec![Color::default(); width * height],
+            |mut a, b| {
+                for (x, y) in a.iter_mut().zip(b) {
+                    *x += y;
+                }
+                a
+            },
+        );
+    let scale = 1. / camera.samples_per_pixel as f64;
+    film.into_iter().map(|c| c * scale).collect()
+}
+
+// Trace one camera and one light subpath for pixel (i, j), and add the light of all their
+// connections to the film
+fn sample(camera: &Camera, world: &Arc<dyn Hittable>, i: usize, j: usize, film: &mut [Color]) {
+    let max_depth = camera.max_depth.max(0) as usize;
+    let ray = camera.get_ray(i as i32, j as i32);
+    let mut camera_path = vec![Vertex::camera(ray.origin(), Color::new(1., 1., 1.))];
+    let mut color = random_walk(
+        camera,
+        world,
+        ray,
+        Color::new(1., 1., 1.),
+        camera.direction_pdf(&ray.direction()),
+        max_depth,
+        &mut camera_path,
+    );
+
+    let light_path = match &camera.lights {
+        Some(lights) => light_subpath(camera, world, lights, ray.time(), max_depth),
+        None => Vec::new(),
+    };
+
+    // The path has s + t - 1 vertices besides the camera, like the max_depth hits allowed for
+    // paths traced from the camera alone
+    for t in 1..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            if s + t > max_depth + 1 || (t == 1 && s < 2) {
+                continue;
+            }
+            let Some(connection) =
+                connect(camera, world, &light_path, &camera_path, s, t, ray.time())
+            else {
+                continue;
+            };
+            let sampled = connection.sampled.as_ref();
+            let light =
+                connection.light * mis_weight(camera, &light_path, &camera_path, sampled, s, t);
+            match connection.pixel {
+                Some((x, y)) => film[y * camera.image_width() + x] += light,
+                None => color += light,
+            }
+        }
+    }
+    film[j * camera.image_width() + i] += color;
+}
+
+// Start a path at a random point on the lights and follow it into the scene
+fn light_subpath(
+    camera: &Camera,
+    world: &Arc<dyn Hittable>,
+    lights: &Arc<dyn Hittable>,
+    time: f64,
+    max_depth: usize,
+) -> Vec<Vertex> {
+    if max_depth == 0 {
+        return Vec::new();
+    }
+    let Some((rec, pdf)) = lights.sample_surface() else {
+        return Vec::new();
+    };
+    if pdf <= 0. {
+        return Vec::new();
+    }
+
+    // Leave the light from either face, cosine weighted around its normal
+    let side = if random_double() < 0.5 {
+        rec.normal
+    } else {
+        -rec.normal
+    };
+    let mut direction = side + Vec3::random_unit_vector();
+    if direction.near_zero() {
+        direction = side;
+    }
+    let pdf_direction = dot(unit_vector(&direction), side) / (2. * PI);
+
+    let light = Vertex::light(rec, pdf);
+    let beta = light.beta * light.f(&(light.p() + direction)) / pdf_direction;
+    let ray = Ray::new_tm(light.p(), direction, time);
+    let mut path = vec![light];
+    random_walk(
+        camera,
+        world,
+        ray,
+        beta,
+        pdf_direction,
+        max_depth - 1,
+        &mut path,
+    );
+    path
+}
+
+// Extend a path by following the rays its materials scatter, adding at most max_vertices
+// vertices. Returns the background seen by camera paths that leave the scene
+fn random_walk(
+    camera: &Camera,
+    world: &Arc<dyn Hittable>,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf: f64,
+    max_vertices: usize,
+    path: &mut Vec<Vertex>,
+) -> Color {
+    for _ in 0..max_vertices {
+        let mut rec: HitRecord = Default::default();
+        if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            if path[0].kind == VertexKind::Camera {
+                return beta * camera.background.value(&ray.direction());
+            }
+            break;
+        }
+        rec.set_footprint(camera.footprint(&rec.p));
+
+        let material = rec.material.clone().unwrap();
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface,
+            rec,
+            r_in: ray,
+            beta,
+            delta: false,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+        };
+        vertex.pdf_fwd = path.last().unwrap().convert_density(pdf, &vertex);
+
+        let Some(srec) = material.scatter(&ray, &vertex.rec) else {
+            path.push(vertex);
+            break;
+        };
+        let (scattered, pdf_fwd, weight) = match (srec.specular_ray, srec.pdf) {
+            (Some(specular_ray), _) => {
+                vertex.delta = true;
+                (specular_ray, 0., srec.attenuation)
+            }
+            (None, Some(material_pdf)) => {
+                let scattered = Ray::new_tm(vertex.p(), material_pdf.generate(), ray.time());
+                let pdf = material_pdf.value(&scattered.direction());
+                if pdf <= 0. {
+                    path.push(vertex);
+                    break;
+                }
+                let f = material.eval(&ray, &vertex.rec, &scattered);
+                (scattered, pdf, f / pdf)
+            }
+            (None, None) => {
+                path.push(vertex);
+                break;
+            }
+        };
+
+        // Density of sampling the way back, from the scattered direction to the previous vertex
+        if !vertex.delta {
+            let back_in = Ray::new_tm(scattered.at(1.), -scattered.direction(), ray.time());
+            let back_out = Ray::new_tm(vertex.p(), -ray.direction(), ray.time());
+            let pdf_rev = material.pdf(&back_in, &vertex.rec, &back_out);
+            let prev = path.len() - 1;
+            path[prev].pdf_rev = vertex.convert_density(pdf_rev, &path[prev]);
+        }
+
+        beta = beta * weight;
+        path.push(vertex);
+        ray = scattered;
+        pdf = pdf_fwd;
+    }
+    Color::default()
+}
+
+fn unoccluded(world: &Arc<dyn Hittable>, a: &Point3, b: &Point3, time: f64) -> bool {
+    let r = Ray::new_tm(*a, *b - *a, time);
+    let epsilon = 0.001 / r.direction().length();
+    let mut rec: HitRecord = Default::default();
+    !world.hit(&r, Interval::new(epsilon, 1. - epsilon), &mut rec)
+}
+
+struct Connection {
+    light: Color,
+    pixel: Option<(usize, usize)>, // where to splat paths connected to the lens
+    sampled: Option<Vertex>,       // new vertex picked on the lens or a light for the connection
+}
+
+// Light carried by the path of the first s light and t camera vertices, None when the path
+// carries nothing
+fn connect(
+    camera: &Camera,
+    world: &Arc<dyn Hittable>,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+    time: f64,
+) -> Option<Connection> {
+    if s == 0 {
+        // The camera path hit a light by itself
+        let pt = &camera_path[t - 1];
+        if pt.kind != VertexKind::Surface {
+            return None;
+        }
+        let emitted = pt
+            .rec
+            .material
+            .as_ref()
+            .unwrap()
+            .emitted(pt.rec.u, pt.rec.v, &pt.p());
+        return Some(Connection {
+            light: pt.beta * emitted,
+            pixel: None,
+            sampled: None,
+        });
+    }
+
+    if t == 1 {
+        // Connect the light path straight to a point on the lens
+        let qs = &light_path[s - 1];
+        if qs.delta {
+            return None;
+        }
+        let lens = camera.sample_lens();
+        let pixel = camera.pixel_for(&lens, &qs.p())?;
+        // Importance of the camera over the solid angle density of picking the lens point as
+        // seen from qs, 1 / (A cos^4) over d^2 / cos
+        let from_lens = qs.p() - lens;
+        let importance = camera.direction_pdf(&from_lens) / from_lens.length_squared();
+        let sampled = Vertex::camera(lens, Color::new(importance, importance, importance));
+        let light = qs.beta * qs.f(&lens) * sampled.beta;
+        if light.near_zero() || !unoccluded(world, &qs.p(), &lens, time) {
+            return None;
+        }
+        return Some(Connection {
+            light,
+            pixel: Some(pixel),
+            sampled: Some(sampled),
+        });
+    }
+
+    let pt = &camera_path[t - 1];
+    if pt.delta || pt.kind != VertexKind::Surface {
+        return None;
+    }
+    let (qs, sampled) = if s == 1 {
+        // Pick a fresh point on the lights for this camera vertex
+        let (rec, pdf) = camera.lights.as_ref()?.sample_surface()?;
+        if pdf <= 0. {
+            return None;
+        }
+        let light = Vertex::light(rec, pdf);
+        (light.clone(), Some(light))
+    } else {
+        let qs = &light_path[s - 1];
+        if qs.delta {
+            return None;
+        }
+        (qs.clone(), None)
+    };
+
+    let distance_squared = (qs.p() - pt.p()).length_squared();
+    let light = qs.beta * qs.f(&pt.p()) * pt.f(&qs.p()) * pt.beta / distance_squared;
+    if light.near_zero() || !unoccluded(world, &qs.p(), &pt.p(), time) {
+        return None;
+    }
+    Some(Connection {
+        light,
+        pixel: None,
+        sampled,
+    })
+}
+
+// Balance heuristic weight of strategy (s, t) among all strategies that build the same path
+fn mis_weight(
+    camera: &Camera,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    sampled: Option<&Vertex>,
+    s: usize,
+    t: usize,
+) -> f64 {
+    if s + t == 2 {
+        return 1.;
+    }
+
+    // The vertices at the connection, with the one that was sampled for it if any
+    let qs = match s {
+        0 => None,
+        1 => sampled,
+        _ => Some(&light_path[s - 1]),
+    };
+    let pt = if t == 1 {
+        sampled.unwrap()
+    } else {
+        &camera_path[t - 1]
+    };
+    let qs_minus = (s > 1).then(|| &light_path[s - 2]);
+    let pt_minus = (t > 1).then(|| &camera_path[t - 2]);
+
+    // Reverse densities of the connection vertices and their neighbours along the joined path
+    let pt_rev = match qs {
+        Some(qs) => qs.pdf(camera, qs_minus, pt),
+        None => {
+            // The light would have had to start its path at pt
+            let pt_minus = pt_minus.unwrap();
+            let origin_pdf = match &camera.lights {
+                Some(lights) => lights.surface_pdf(&pt_minus.p(), &pt.p()),
+                None => 0.,
+            };
+            if origin_pdf <= 0. {
+                // Not one of the lights, nothing else could have found this path
+                return 1.;
+            }
+            origin_pdf
+        }
+    };
+    let pt_minus_rev = pt_minus.map(|pt_minus| match qs {
+        Some(qs) => pt.pdf(camera, Some(qs), pt_minus),
+        None => {
+            let light = Vertex {
+                kind: VertexKind::Light,
+                ..pt.clone()
+            };
+            light.pdf(camera, None, pt_minus)
+        }
+    });
+    let qs_rev = qs.map(|qs| pt.pdf(camera, pt_minus, qs));
+    let qs_minus_rev = qs_minus.map(|qs_minus| qs.unwrap().pdf(camera, Some(pt), qs_minus));
+
+    // Delta vertices have no density, they cancel out of the ratios
+    let remap = |pdf: f64| if pdf == 0. { 1. } else { pdf };
+    let mut sum_ri = 0.;
+
+    // Move the connection towards the camera, one vertex at a time
+    let mut ri = 1.;
+    for i in (1..t).rev() {
+        let v = &camera_path[i];
+        let (pdf_rev, delta) = if i == t - 1 {
+            (pt_rev, false)
+        } else if i == t - 2 {
+            (pt_minus_rev.unwrap(), v.delta)
+        } else {
+            (v.pdf_rev, v.delta)
+        };
+        ri *= remap(pdf_rev) / remap(v.pdf_fwd);
+        if !delta && !camera_path[i - 1].delta {
+            sum_ri += ri;
+        }
+    }
+
+    // Move the connection towards the light
+    ri = 1.;
+    for i in (0..s).rev() {
+        let v = if i == s - 1 {
+            qs.unwrap()
+        } else {
+            &light_path[i]
+        };
+        let (pdf_rev, delta) = if i == s - 1 {
+            (qs_rev.unwrap(), false)
+        } else if i == s - 2 {
+            (qs_minus_rev.unwrap(), v.delta)
+        } else {
+            (v.pdf_rev, v.delta)
+        };
+        ri *= remap(pdf_rev) / remap(v.pdf_fwd);
+        let prev_delta = i > 0 && light_path[i - 1].delta;
+        if !delta && !prev_delta {
+            sum_ri += ri;
+        }
+    }
+
+    1. / (1. + sum_ri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        background::Background,
+        camera::Integrator,
+        hittable::HittableList,
+        material::{DiffuseLight, Lambertian},
+        quad::Quad,
+        sphere::Sphere,
+    };
+
+    // A diffuse sphere on a diffuse floor under a small quad light, with nothing around
+    fn diffuse_scene(integrator: Integrator) -> (Camera, Arc<dyn Hittable>) {
+        let mut camera = Camera::new(
+            8,
+            4. / 3.,
+            2000,
+            4,
+            60.,
+            Point3::new(0., 1., 3.),
+            Point3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            0.,
+            4.,
+        );
+        camera.background = Background::Solid(Color::default());
+        camera.integrator = integrator;
+
+        let mut world = HittableList::new();
+        let white = Arc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7)));
+        world.add(Arc::new(Quad::new(
+            Point3::new(-3., 0., -3.),
+            Vec3::new(0., 0., 6.),
+            Vec3::new(6., 0., 0.),
+            white.clone(),
+        )));
+        world.add(Arc::new(Sphere::new(Point3::new(0., 0.5, 0.), 0.5, white)));
+        let light: Arc<dyn Hittable> = Arc::new(Quad::new(
+            Point3::new(-0.5, 2., -0.5),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            Arc::new(DiffuseLight::new(Color::new(8., 8., 8.))),
+        ));
+        world.add(light.clone());
+        camera.lights = Some(light);
+        (camera, Arc::new(world))
+    }
+
+    #[test]
+    fn converges_to_path_tracing() {
+        let (camera, world) = diffuse_scene(Integrator::PathTracing);
+        let expected = camera.render(&world);
+        let (camera, world) = diffuse_scene(Integrator::Bidirectional);
+        let image = camera.render(&world);
+
+        // Both are noisy, but should agree closely on average and roughly on every pixel
+        let mean = |pixels: &[Color]| pixels.iter().map(|c| c.x()).sum::<f64>() / 48.;
+        let (a, b) = (mean(&expected), mean(&image));
+        assert!(f64::abs(a - b) < 0.02 * a, "{} vs {}", a, b);
+        for (p, q) in expected.iter().zip(&image) {
+            assert!(
+                f64::abs(p.x() - q.x()) < 0.1 * p.x() + 0.01,
+                "{} vs {}",
+                p,
+                q
+            );
+        }
+    }
+}
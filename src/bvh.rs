@@ -7,6 +7,22 @@ use crate::{
     ray::Ray,
 };
 
+// Number of buckets the centroids are sorted into when evaluating SAH splits on an axis
+const SAH_BUCKETS: usize = 12;
+// Cost of visiting a node relative to the cost of intersecting one primitive
+const SAH_TRAVERSAL_COST: f64 = 0.125;
+// Largest number of primitives the SAH builder will put in a single leaf
+pub const SAH_MAX_LEAF_OBJECTS: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitMethod {
+    // Sort on the longest axis and split at the median, one or two objects per leaf
+    #[default]
+    Median,
+    // Binned surface area heuristic, leaves may hold several objects
+    Sah,
+}
+
 #[derive(Debug)]
 pub struct BVHNode {
     left: Arc<dyn Hittable>,
@@ -20,6 +36,13 @@ impl BVHNode {
         Self::construct(&mut list.objects, 0, len)
     }
 
+    pub fn with_split_method(list: &mut HittableList, method: SplitMethod) -> Arc<dyn Hittable> {
+        match method {
+            SplitMethod::Median => Self::new(list),
+            SplitMethod::Sah => Self::construct_sah(&mut list.objects),
+        }
+    }
+
     pub fn construct(objects: &mut Vec<Arc<dyn Hittable>>, start: usize, end: usize) -> Arc<Self> {
         // We create a bounding box with all the elements of the objects list
         let mut bbox = AABB::empty();
@@ -59,6 +82,121 @@ impl BVHNode {
         }
         Arc::new(Self { left, right, bbox })
     }
+
+    fn construct_sah(objects: &mut [Arc<dyn Hittable>]) -> Arc<dyn Hittable> {
+        if objects.len() == 1 {
+            return objects[0].clone();
+        }
+        let mut bbox = AABB::empty();
+        for obj in objects.iter() {
+            bbox = AABB::with_boxes(&bbox, &obj.bounding_box());
+        }
+
+        let mid = match sah_split(objects, &bbox, |obj| obj.bounding_box()) {
+            Some((mid, _)) => mid,
+            None if objects.len() <= SAH_MAX_LEAF_OBJECTS => {
+                // Splitting costs more than testing every object, keep them together in a leaf
+                let mut leaf = HittableList::new();
+                for obj in objects.iter() {
+                    leaf.add(obj.clone());
+                }
+                return Arc::new(leaf);
+            }
+            None => {
+                // Too many objects for a leaf but no useful split, e.g. all centroids are at the
+                // same point. Fall back to splitting the list in half
+                objects.sort_by(|a, b| HittableAxisCompare::box_compare(a, b, bbox.longest_axis()));
+                objects.len() / 2
+            }
+        };
+
+        let (left_objects, right_objects) = objects.split_at_mut(mid);
+        let left = Self::construct_sah(left_objects);
+        let right = Self::construct_sah(right_objects);
+        Arc::new(Self { left, right, bbox })
+    }
+}
+
+/// Find the cheapest split of the items according to the surface area heuristic and partition
+/// them around it. Returns the index of the first item on the right side along with the split
+/// axis, or None if keeping every item in a single leaf is cheaper or no split is possible.
+pub fn sah_split<T>(
+    items: &mut [T],
+    bbox: &AABB,
+    bounding_box: impl Fn(&T) -> AABB,
+) -> Option<(usize, usize)> {
+    let mut boxes: Vec<AABB> = items.iter().map(bounding_box).collect();
+    let mut centroid_bbox = AABB::empty();
+    for b in boxes.iter() {
+        let c = b.centroid();
+        centroid_bbox = AABB::with_boxes(&centroid_bbox, &AABB::with_points(&c, &c));
+    }
+
+    let bucket_of = |axis: usize, b: &AABB| {
+        let extent = centroid_bbox.axis_interval(axis as i32);
+        let offset = (b.centroid()[axis] - extent.min) / extent.size();
+        ((offset * SAH_BUCKETS as f64) as usize).min(SAH_BUCKETS - 1)
+    };
+
+    let leaf_cost = items.len() as f64;
+    let parent_area = bbox.surface_area();
+    let mut best: Option<(f64, usize, usize)> = None; // (cost, axis, first right bucket)
+    for axis in 0..3 {
+        if centroid_bbox.axis_interval(axis as i32).size() <= 0. {
+            continue;
+        }
+
+        let mut counts = [0usize; SAH_BUCKETS];
+        let mut bucket_boxes = [AABB::empty(); SAH_BUCKETS];
+        for b in boxes.iter() {
+            let bucket = bucket_of(axis, b);
+            counts[bucket] += 1;
+            bucket_boxes[bucket] = AABB::with_boxes(&bucket_boxes[bucket], b);
+        }
+
+        // Sweep from the right to get the area and count of every suffix of buckets, then
+        // from the left to evaluate each split between buckets
+        let mut right_area = [0.; SAH_BUCKETS];
+        let mut right_count = [0usize; SAH_BUCKETS];
+        let (mut acc_box, mut acc_count) = (AABB::empty(), 0);
+        for b in (1..SAH_BUCKETS).rev() {
+            acc_box = AABB::with_boxes(&acc_box, &bucket_boxes[b]);
+            acc_count += counts[b];
+            right_area[b] = acc_box.surface_area();
+            right_count[b] = acc_count;
+        }
+        let (mut acc_box, mut acc_count) = (AABB::empty(), 0);
+        for b in 1..SAH_BUCKETS {
+            acc_box = AABB::with_boxes(&acc_box, &bucket_boxes[b - 1]);
+            acc_count += counts[b - 1];
+            if acc_count == 0 || right_count[b] == 0 {
+                continue;
+            }
+            let cost = SAH_TRAVERSAL_COST
+                + (acc_box.surface_area() * acc_count as f64
+                    + right_area[b] * right_count[b] as f64)
+                    / parent_area;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, b));
+            }
+        }
+    }
+
+    let (cost, axis, split_bucket) = best?;
+    if items.len() <= SAH_MAX_LEAF_OBJECTS && cost >= leaf_cost {
+        return None;
+    }
+
+    // Partition in place, items whose centroid falls in a bucket left of the split first
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bucket_of(axis, &boxes[i]) < split_bucket {
+            items.swap(i, mid);
+            boxes.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((mid, axis))
 }
 
 impl Hittable for BVHNode {
@@ -78,3 +216,68 @@ impl Hittable for BVHNode {
         self.bbox
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color, material::Lambertian, ray::Point3, sphere::Sphere,
+        utils::random_double_range, vec3::Vec3,
+    };
+
+    fn random_spheres(n: usize) -> HittableList {
+        let mut world = HittableList::new();
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        for _ in 0..n {
+            let center = Point3::random_range(-10., 10.);
+            let radius = random_double_range(0.05, 1.);
+            world.add(Arc::new(Sphere::new(center, radius, material.clone())));
+        }
+        world
+    }
+
+    #[test]
+    fn sah_matches_brute_force() {
+        let mut world = random_spheres(300);
+        let brute_force: Vec<_> = world.objects.clone();
+        let bvh = BVHNode::with_split_method(&mut world, SplitMethod::Sah);
+
+        for _ in 0..500 {
+            let r = Ray::new(Point3::random_range(-12., 12.), Vec3::random_unit_vector());
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+
+            let mut expected = HitRecord::default();
+            let mut closest = ray_t.max;
+            for obj in &brute_force {
+                if obj.hit(&r, Interval::new(ray_t.min, closest), &mut expected) {
+                    closest = expected.t;
+                }
+            }
+
+            let mut rec = HitRecord::default();
+            assert_eq!(bvh.hit(&r, ray_t, &mut rec), closest < f64::INFINITY);
+            if closest < f64::INFINITY {
+                assert_eq!(rec.t, expected.t);
+            }
+        }
+    }
+
+    #[test]
+    fn sah_handles_coincident_centroids() {
+        let mut world = HittableList::new();
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        for i in 0..20 {
+            let radius = 1. + i as f64 * 0.1;
+            world.add(Arc::new(Sphere::new(
+                Point3::new(0., 0., 0.),
+                radius,
+                material.clone(),
+            )));
+        }
+        let bvh = BVHNode::with_split_method(&mut world, SplitMethod::Sah);
+        let r = Ray::new(Point3::new(0., 0., 10.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(bvh.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!(f64::abs(rec.t - (10. - 2.9)) < 1e-9);
+    }
+}
@@ -1,19 +1,33 @@
 use serde::Serialize;
 
 use crate::{
+    background::Background,
+    bdpt,
     color::Color,
     hittable::{HitRecord, Hittable, HittableList},
     interval::Interval,
+    pdf::{HittablePdf, MixturePdf, Pdf},
     ray::{Point3, Ray},
     sphere::hit_sphere,
     utils::{degrees_to_radians, random_double},
-    vec3::{cross, unit_vector, Vec3},
+    vec3::{cross, dot, unit_vector, Vec3},
 };
 
 use rayon::prelude::*;
 
 use std::sync::Arc;
 
+// How the camera estimates the light arriving through each pixel
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    // Paths are traced from the camera only, with the lights sampled at every diffuse bounce
+    #[default]
+    PathTracing,
+    // Paths are traced from both the camera and the lights and connected, see bdpt.rs. Much
+    // better at caustics and at light that comes in through small openings
+    Bidirectional,
+}
+
 #[derive(Serialize)]
 pub struct Camera {
     pub image_width: i32,
@@ -25,6 +39,12 @@ pub struct Camera {
     pub lookfrom: Point3, // point where camera is looking from
     pub lookat: Point3,   // point where camera is looking at
     pub vup: Vec3,        // rotation angle of camera
+    #[serde(skip)]
+    pub background: Background, // what rays see when they escape the scene
+    #[serde(skip)]
+    pub lights: Option<Arc<dyn Hittable>>, // emitters sampled directly at every diffuse bounce
+    #[serde(skip)]
+    pub integrator: Integrator,
 
     u: Vec3, // camera frame basis vectors
     v: Vec3,
@@ -98,6 +118,9 @@ impl Camera {
             lookfrom,
             lookat,
             vup,
+            background: Background::default(),
+            lights: None,
+            integrator: Integrator::default(),
             u,
             v,
             w,
@@ -121,6 +144,9 @@ impl Camera {
     }
 
     pub fn render(&self, world: &Arc<dyn Hittable>) -> Vec<Color> {
+        if self.integrator == Integrator::Bidirectional {
+            return bdpt::render(self, world);
+        }
         return (0..self.image_height)
             .into_par_iter()
             .flat_map(|j| {
@@ -143,6 +169,19 @@ impl Camera {
     }
 
     pub fn ray_color(&self, ray: Ray, world: &Arc<dyn Hittable>, depth: i32) -> Color {
+        self.trace(ray, world, depth, None)
+    }
+
+    // bsdf_pdf is the density the previous bounce sampled this ray with, when that bounce also
+    // sampled the lights directly. Light hit by this ray then has to be weighted against the
+    // light sample, so the same light is not counted twice
+    fn trace(
+        &self,
+        ray: Ray,
+        world: &Arc<dyn Hittable>,
+        depth: i32,
+        bsdf_pdf: Option<f64>,
+    ) -> Color {
         if depth <= 0 {
             return Color::default();
         }
@@ -150,28 +189,76 @@ impl Camera {
 
         // Fix for shadow acne, due to floating point rounding errors, the reflected ray might end
         // up being under surface of the object, we limit the minimum intersect distance
-        if world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
-            // let direction = Vec3::random_on_hemisphere(*rec.normal); --- Uniform Reflection
-            // let direction = rec.normal + Vec3::random_unit_vector(); // Lambertian Reflection
-            let mut scattered = Ray::default();
-            let mut attenuation = Color::default();
-            if rec
-                .material
-                .as_ref()
-                .unwrap()
-                .scatter(&ray, &rec, &mut attenuation, &mut scattered)
+        if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return self.background.value(&ray.direction());
+        }
+        rec.set_footprint(self.footprint(&rec.p));
+
+        let material = rec.material.as_ref().unwrap();
+        // Emitted light is added at every bounce, on top of whatever gets scattered
+        let mut color_from_emission = material.emitted(rec.u, rec.v, &rec.p);
+        if let (Some(bsdf_pdf), Some(lights)) = (bsdf_pdf, &self.lights) {
+            let light_pdf = lights.pdf_value(&ray.origin(), &ray.direction());
+            color_from_emission *= power_heuristic(bsdf_pdf, light_pdf);
+        }
+
+        let Some(srec) = material.scatter(&ray, &rec) else {
+            return color_from_emission;
+        };
+        if let Some(specular_ray) = srec.specular_ray {
+            // Mirror-like bounce, neither lights nor the environment can be sampled for it
+            return color_from_emission
+                + srec.attenuation * self.trace(specular_ray, world, depth - 1, None);
+        }
+        let Some(material_pdf) = srec.pdf else {
+            return color_from_emission;
+        };
+
+        // Diffuse bounce under an environment map: half of the time follow the material, half of
+        // the time aim for the bright parts of the map, and weight the result by the combined
+        // probability of both strategies
+        let sampling_pdf: Arc<dyn Pdf> = match &self.background {
+            Background::Environment(env) => Arc::new(MixturePdf::new(material_pdf, env.clone())),
+            _ => material_pdf,
+        };
+
+        // Next event estimation: aim a shadow ray at a random point on the lights, and add
+        // whatever it reaches if that is an emitter
+        let mut color_from_lights = Color::default();
+        if let Some(lights) = &self.lights {
+            let light_pdf = HittablePdf::new(lights.clone(), rec.p);
+            let to_light = Ray::new_tm(rec.p, light_pdf.generate(), ray.time());
+            let pdf = light_pdf.value(&to_light.direction());
+            let f = material.eval(&ray, &rec, &to_light);
+            let mut light_rec: HitRecord = Default::default();
+            if pdf > 0.
+                && f != Color::default()
+                && world.hit(
+                    &to_light,
+                    Interval::new(0.001, f64::INFINITY),
+                    &mut light_rec,
+                )
             {
-                return attenuation * self.ray_color(scattered, world, depth - 1);
+                let light_material = light_rec.material.as_ref().unwrap();
+                let emitted = light_material.emitted(light_rec.u, light_rec.v, &light_rec.p);
+                let weight = power_heuristic(pdf, sampling_pdf.value(&to_light.direction()));
+                color_from_lights = f * emitted * (weight / pdf);
             }
-            return Color::default();
         }
 
-        let unit_direction = unit_vector(&ray.direction());
-        let a = 0.5 * (unit_direction.y() + 1.0);
-        return Color::new(1., 1., 1.) * (1. - a) + Color::new(0.5, 0.7, 1.) * a;
+        let scattered = Ray::new_tm(rec.p, sampling_pdf.generate(), ray.time());
+        let pdf = sampling_pdf.value(&scattered.direction());
+        let f = material.eval(&ray, &rec, &scattered);
+        if pdf <= 0. || f == Color::default() {
+            return color_from_emission + color_from_lights;
+        }
+        let next_bsdf_pdf = self.lights.as_ref().map(|_| pdf);
+        color_from_emission
+            + color_from_lights
+            + f * self.trace(scattered, world, depth - 1, next_bsdf_pdf) / pdf
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray {
+    pub fn get_ray(&self, i: i32, j: i32) -> Ray {
         // Construct a camera ray originating from the defocus disk, and directed at a randomly
         // sampled point around the pixel location i, j
         let offset = sample_square();
@@ -194,6 +281,53 @@ impl Camera {
         self.lookfrom + (self.defocus_disk_u * p[0]) + (self.defocus_disk_v * p[1])
     }
 
+    // A random point on the lens, where camera rays start
+    pub fn sample_lens(&self) -> Point3 {
+        if self.defocus_angle <= 0. {
+            self.lookfrom
+        } else {
+            self.defocus_disk_sample()
+        }
+    }
+
+    // Width of the patch of surface at p one pixel covers, seen from the camera. Used to filter
+    // textures at every bounce, which blurs them a little less than tracking how the footprint
+    // spreads through each reflection would but is much cheaper
+    pub fn footprint(&self, p: &Point3) -> f64 {
+        (*p - self.lookfrom).length() * self.pixel_delta_u.length() / self.focus_dist
+    }
+
+    // The pixel (i, j) that light travelling from p to the given point on the lens is recorded
+    // in, None if it lands outside the image. Inverse of get_ray()
+    pub fn pixel_for(&self, lens_point: &Point3, p: &Point3) -> Option<(usize, usize)> {
+        let direction = *p - *lens_point;
+        let depth = dot(direction, -self.w);
+        if depth <= 0. {
+            return None;
+        }
+        // Where the ray crosses the plane of perfect focus, which get_ray() aims at
+        let on_focus_plane = *lens_point + direction * (self.focus_dist / depth);
+        let offset = on_focus_plane - self.pixel00_loc;
+        let x = dot(offset, self.pixel_delta_u) / self.pixel_delta_u.length_squared() + 0.5;
+        let y = dot(offset, self.pixel_delta_v) / self.pixel_delta_v.length_squared() + 0.5;
+        if x < 0. || y < 0. || x >= self.image_width as f64 || y >= self.image_height as f64 {
+            return None;
+        }
+        Some((x as usize, y as usize))
+    }
+
+    // Solid angle density of get_ray() picking the given direction when the pixel is picked at
+    // random too. The image has area A at distance one from the lens, and a patch of it covers a
+    // solid angle that shrinks with cos^3 of its angle to the view direction
+    pub fn direction_pdf(&self, direction: &Vec3) -> f64 {
+        let cos_theta = dot(unit_vector(direction), -self.w);
+        if cos_theta <= 0. {
+            return 0.;
+        }
+        let area = self.viewport_width * self.viewport_height / (self.focus_dist * self.focus_dist);
+        1. / (area * cos_theta.powi(3))
+    }
+
     pub fn image_width(&self) -> usize {
         self.image_width as usize
     }
@@ -202,6 +336,16 @@ impl Camera {
     }
 }
 
+// Multiple importance sampling weight of a sample taken with density pdf, when another strategy
+// could have produced the same sample with density other_pdf
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b <= 0. {
+        return 0.;
+    }
+    a / (a + b)
+}
+
 fn sample_square() -> Vec3 {
     Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.)
 }
@@ -211,4 +355,76 @@ mod camera {
     //Test viewport calculations
     //Test pixel00 calculation
     //Test focal length calculation
+    use super::*;
+    use crate::{
+        material::{DiffuseLight, Lambertian},
+        quad::Quad,
+    };
+
+    // Average of many single bounce estimates at the point where a ray hits a floor that is lit
+    // by a small quad light
+    fn direct_light_estimate(sample_lights: bool) -> f64 {
+        let mut camera = Camera::new(
+            1,
+            1.,
+            1,
+            2,
+            90.,
+            Point3::new(0., 0.5, 1.),
+            Point3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            0.,
+            1.,
+        );
+        camera.background = Background::Solid(Color::default());
+
+        let mut world = HittableList::new();
+        world.add(Arc::new(Quad::new(
+            Point3::new(-5., 0., -5.),
+            Vec3::new(0., 0., 10.),
+            Vec3::new(10., 0., 0.),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        let light: Arc<dyn Hittable> = Arc::new(Quad::new(
+            Point3::new(-0.1, 1., -0.1),
+            Vec3::new(0.2, 0., 0.),
+            Vec3::new(0., 0., 0.2),
+            Arc::new(DiffuseLight::new(Color::new(10., 10., 10.))),
+        ));
+        world.add(light.clone());
+        if sample_lights {
+            camera.lights = Some(light);
+        }
+        let world: Arc<dyn Hittable> = Arc::new(world);
+
+        let samples = 200_000;
+        let total: f64 = (0..samples)
+            .into_par_iter()
+            .map(|_| {
+                let r = Ray::new(Point3::new(0., 0.5, 1.), Vec3::new(0., -0.5, -1.));
+                camera.ray_color(r, &world, camera.max_depth).x()
+            })
+            .sum();
+        total / samples as f64
+    }
+
+    #[test]
+    fn light_sampling_matches_path_tracing() {
+        // albedo / pi * radiance * solid angle of the light, nearly straight above the point
+        let expected = 0.5 / std::f64::consts::PI * 10. * 0.04;
+        let with_lights = direct_light_estimate(true);
+        let without_lights = direct_light_estimate(false);
+        assert!(
+            f64::abs(with_lights - expected) < 0.03 * expected,
+            "{} vs {}",
+            with_lights,
+            expected
+        );
+        assert!(
+            f64::abs(with_lights - without_lights) < 0.1 * expected,
+            "{} vs {}",
+            with_lights,
+            without_lights
+        );
+    }
 }
@@ -7,9 +7,9 @@ impl Color {
         let g = self.y();
         let b = self.z();
 
-        let rg = linear_to_gamma(r);
-        let gg = linear_to_gamma(g);
-        let bg = linear_to_gamma(b);
+        let rg = linear_to_srgb(r);
+        let gg = linear_to_srgb(g);
+        let bg = linear_to_srgb(b);
 
         let intensity = Interval::new(0.000, 0.999);
         let rbyte = (256. * intensity.clamp(rg)) as u8;
@@ -24,10 +24,40 @@ impl Color {
     }
 }
 
-// Linear correction for more consistent ramp from darkness to lightness
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    if linear_component > 0. {
-        return f64::sqrt(linear_component);
+// Encodes a linear intensity with the sRGB transfer curve displays expect, for a more even ramp
+// from darkness to lightness. Exact inverse of srgb_to_linear, so textures shown under white
+// light of unit intensity come out as they went in
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0. {
+        0.
+    } else if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1. / 2.4) - 0.055
+    }
+}
+
+// Decodes an sRGB encoded value in [0, 1], as stored in most 8 bit images, to linear intensity
+pub fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips() {
+        for i in 0..=255 {
+            let encoded = i as f64 / 255.;
+            let decoded = srgb_to_linear(encoded);
+            assert!(f64::abs(linear_to_srgb(decoded) - encoded) < 1e-9);
+        }
+        assert!(f64::abs(srgb_to_linear(0.5) - 0.214) < 1e-3);
+        assert_eq!(Color::new(1., 0.5, 0.).get_rgb(), [255, 188, 0]);
     }
-    return 0.;
 }
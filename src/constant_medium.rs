@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    color::Color,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::{Isotropic, Material},
+    ray::Ray,
+    texture::Texture,
+    utils::random_double,
+    vec3::Vec3,
+};
+
+/// A volume of constant density filling a boundary shape, such as fog or smoke.
+/// A ray passing through the volume scatters after a random, exponentially distributed
+/// distance, or passes through unaffected if that distance is past the far side of the boundary.
+/// The boundary must be convex, since only its first entry and exit points are considered.
+#[derive(Debug)]
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Option<Arc<dyn Material>>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, tex: Arc<dyn Texture>) -> Self {
+        Self {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase_function: Some(Arc::new(Isotropic::with_texture(tex))),
+        }
+    }
+
+    pub fn with_color(boundary: Arc<dyn Hittable>, density: f64, albedo: Color) -> Self {
+        Self {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase_function: Some(Arc::new(Isotropic::new(albedo))),
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Find where the ray enters and leaves the boundary, looking along the whole ray since
+        // the ray may start inside the volume
+        let mut rec1 = HitRecord::default();
+        let mut rec2 = HitRecord::default();
+        if !self.boundary.hit(r, Interval::universe(), &mut rec1) {
+            return false;
+        }
+        if !self
+            .boundary
+            .hit(r, Interval::new(rec1.t + 0.0001, f64::INFINITY), &mut rec2)
+        {
+            return false;
+        }
+
+        // Clip the segment inside the volume to the accepted interval
+        rec1.t = rec1.t.max(ray_t.min);
+        rec2.t = rec2.t.min(ray_t.max);
+        if rec1.t >= rec2.t {
+            return false;
+        }
+        rec1.t = rec1.t.max(0.);
+
+        let ray_length = r.direction().length();
+        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
+        let hit_distance = self.neg_inv_density * f64::ln(random_double());
+        if hit_distance > distance_inside_boundary {
+            return false;
+        }
+
+        rec.t = rec1.t + hit_distance / ray_length;
+        rec.p = r.at(rec.t);
+        // The normal and face are meaningless inside a volume, the isotropic phase function
+        // ignores them
+        rec.normal = Vec3::new(1., 0., 0.);
+        rec.front_face = true;
+        rec.material = self.phase_function.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, ray::Point3, sphere::Sphere};
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(
+            Point3::new(0., 0., 0.),
+            1.,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    #[test]
+    fn scatters_inside_boundary() {
+        let fog = ConstantMedium::with_color(unit_sphere(), 0.5, Color::new(1., 1., 1.));
+        let r = Ray::new(Point3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let mut rec = HitRecord::default();
+            if fog.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                hits += 1;
+                assert!(rec.t >= 4. && rec.t <= 6.);
+            }
+        }
+        // The chance of scattering across a thickness of 2 is 1 - exp(-0.5 * 2) ~ 0.63
+        assert!(hits > 550 && hits < 710, "hits = {}", hits);
+    }
+
+    #[test]
+    fn ray_starting_inside_scatters_ahead() {
+        let smoke = ConstantMedium::with_color(unit_sphere(), 1e6, Color::new(1., 1., 1.));
+        let r = Ray::new(Point3::new(0., 0., 0.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(smoke.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!(rec.t < 0.01);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{f64::consts::PI, path::Path};
+
+use crate::{
+    background::{direction_to_uv, uv_to_direction},
+    color::Color,
+    pdf::Pdf,
+    utils::random_double,
+    vec3::{unit_vector, Vec3},
+};
+
+/// An equirectangular (latitude-longitude) HDR panorama used to light the scene.
+/// Pixels are kept as linear floats, and a luminance-based distribution is precomputed so that
+/// bright regions such as the sun can be sampled directly instead of found by chance.
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 3]>,
+    // Cumulative distribution over rows, height + 1 entries from 0 to 1
+    marginal_cdf: Vec<f64>,
+    // Cumulative distribution over the pixels of each row, width + 1 entries per row
+    conditional_cdf: Vec<f64>,
+    // Probability of picking each pixel
+    pixel_probability: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    // Load a .hdr or .exr panorama. Other image formats load too, but their values are only
+    // linear if the file itself stores linear data
+    pub fn open(path: &Path) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image.pixels().map(|p| p.0).collect();
+        Ok(Self::from_pixels(width, height, pixels))
+    }
+
+    // Build a map from linear RGB pixels stored row by row, starting with the top row
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<[f32; 3]>) -> Self {
+        assert!(width > 0 && height > 0 && pixels.len() == width * height);
+
+        // Weight each pixel by its luminance and by the solid angle it covers, which shrinks
+        // towards the poles of the panorama
+        let mut weights = vec![0.; width * height];
+        for j in 0..height {
+            let latitude = PI * (0.5 - (j as f64 + 0.5) / height as f64);
+            let solid_angle = f64::cos(latitude);
+            for i in 0..width {
+                weights[j * width + i] = luminance(&pixels[j * width + i]) * solid_angle;
+            }
+        }
+        let total: f64 = weights.iter().sum();
+        if total <= 0. {
+            // A black map, fall back to picking every pixel equally
+            weights.fill(1.);
+        }
+        let total: f64 = weights.iter().sum();
+
+        let mut marginal_cdf = vec![0.; height + 1];
+        let mut conditional_cdf = vec![0.; (width + 1) * height];
+        for j in 0..height {
+            let row = &weights[j * width..(j + 1) * width];
+            let row_total: f64 = row.iter().sum();
+            marginal_cdf[j + 1] = marginal_cdf[j] + row_total / total;
+
+            let cdf = &mut conditional_cdf[j * (width + 1)..(j + 1) * (width + 1)];
+            for i in 0..width {
+                cdf[i + 1] = if row_total > 0. {
+                    cdf[i] + row[i] / row_total
+                } else {
+                    (i + 1) as f64 / width as f64
+                };
+            }
+        }
+        let pixel_probability = weights.iter().map(|w| w / total).collect();
+
+        Self {
+            width,
+            height,
+            pixels,
+            marginal_cdf,
+            conditional_cdf,
+            pixel_probability,
+        }
+    }
+
+    fn pixel_index(&self, direction: &Vec3) -> usize {
+        let (u, v) = direction_to_uv(&unit_vector(direction));
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = (((1. - v) * self.height as f64) as usize).min(self.height - 1);
+        j * self.width + i
+    }
+
+    pub fn value(&self, direction: &Vec3) -> Color {
+        let [r, g, b] = self.pixels[self.pixel_index(direction)];
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    // Pick a direction with probability proportional to the brightness of the map
+    pub fn sample(&self) -> Vec3 {
+        let j = sample_cdf(&self.marginal_cdf, random_double());
+        let row_cdf = &self.conditional_cdf[j * (self.width + 1)..(j + 1) * (self.width + 1)];
+        let i = sample_cdf(row_cdf, random_double());
+
+        // Jitter uniformly inside the chosen pixel
+        let u = (i as f64 + random_double()) / self.width as f64;
+        let v = 1. - (j as f64 + random_double()) / self.height as f64;
+        uv_to_direction(u, v)
+    }
+
+    // Solid angle probability density of sample() returning the given direction
+    pub fn pdf(&self, direction: &Vec3) -> f64 {
+        let cos_latitude = f64::sqrt(1. - unit_vector(direction).y().powi(2));
+        if cos_latitude <= 0. {
+            return 0.;
+        }
+        // Density over the unit (u, v) square, converted to solid angle with
+        // d_omega = 2 * pi * pi * cos(latitude) * du * dv
+        let uv_pdf =
+            self.pixel_probability[self.pixel_index(direction)] * (self.width * self.height) as f64;
+        uv_pdf / (2. * PI * PI * cos_latitude)
+    }
+}
+
+impl Pdf for EnvironmentMap {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.pdf(direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.sample()
+    }
+}
+
+fn luminance(rgb: &[f32; 3]) -> f64 {
+    0.2126 * rgb[0] as f64 + 0.7152 * rgb[1] as f64 + 0.0722 * rgb[2] as f64
+}
+
+// Index of the bucket of a cumulative distribution that contains x, skipping empty buckets
+fn sample_cdf(cdf: &[f64], x: f64) -> usize {
+    let buckets = cdf.len() - 1;
+    let index = cdf.partition_point(|&c| c <= x).saturating_sub(1);
+    index.min(buckets - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A dim map with one very bright pixel, like a sun in the sky
+    fn sun_map() -> EnvironmentMap {
+        let (width, height) = (32, 16);
+        let mut pixels = vec![[0.1, 0.1, 0.1]; width * height];
+        pixels[4 * width + 20] = [5000., 5000., 5000.];
+        EnvironmentMap::from_pixels(width, height, pixels)
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let env = sun_map();
+        // Midpoint rule over a grid finer than the map, with d_omega = 2 pi^2 cos(lat) du dv
+        let n = 256;
+        let mut integral = 0.;
+        for j in 0..n {
+            for i in 0..n {
+                let (u, v) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let cos_latitude = f64::cos((v - 0.5) * PI);
+                let d_omega = 2. * PI * PI * cos_latitude / (n * n) as f64;
+                integral += env.pdf(&uv_to_direction(u, v)) * d_omega;
+            }
+        }
+        assert!(f64::abs(integral - 1.) < 1e-3, "integral = {}", integral);
+    }
+
+    #[test]
+    fn samples_favor_bright_pixels() {
+        let env = sun_map();
+        let sun = 4 * 32 + 20;
+        let hits = (0..1000)
+            .filter(|_| env.pixel_index(&env.sample()) == sun)
+            .count();
+        assert!(hits > 900);
+        assert!(env.value(&env.sample()).x() > 0.);
+    }
+}
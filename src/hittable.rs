@@ -4,8 +4,10 @@ use crate::{
     aabb::AABB,
     interval::Interval,
     material::Material,
+    onb::Onb,
     ray::{Point3, Ray},
-    vec3::{dot, Vec3},
+    utils::random_int,
+    vec3::{cross, dot, unit_vector, Vec3},
 };
 
 #[derive(Debug, Clone, Default)]
@@ -17,6 +19,16 @@ pub struct HitRecord {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    // Unit shading frame along increasing u and v, perpendicular to the normal. Unlike the normal
+    // they belong to the surface and aren't flipped to face the ray. Zero when the surface has no
+    // parameterization
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    // How far u and v move per unit of distance along the surface, zero when unknown
+    pub uv_per_unit: f64,
+    // Width in uv units of the patch of surface the incoming ray stands for, used to filter
+    // textures. Zero, the sharpest, unless the integrator sets it
+    pub footprint: f64,
 }
 
 impl HitRecord {
@@ -30,6 +42,37 @@ impl HitRecord {
             -*outward_normal
         }
     }
+
+    // Sets the tangent frame from the derivatives of the hit point along u and v, made
+    // perpendicular to the normal, and uv_per_unit from their lengths. Call after the normal
+    // is set
+    pub fn set_tangent_frame(&mut self, dpdu: &Vec3, dpdv: &Vec3) {
+        let outward_normal = if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        };
+        let tangent = *dpdu - outward_normal * dot(outward_normal, *dpdu);
+        // Degenerate parameterizations, such as at the poles of a sphere, get any frame
+        self.tangent = if tangent.near_zero() {
+            Onb::new(&outward_normal).u()
+        } else {
+            unit_vector(&tangent)
+        };
+        let bitangent = cross(outward_normal, self.tangent);
+        self.bitangent = if dot(bitangent, *dpdv) < 0. {
+            -bitangent
+        } else {
+            bitangent
+        };
+        let area = dpdu.length() * dpdv.length();
+        self.uv_per_unit = if area > 0. { 1. / f64::sqrt(area) } else { 0. };
+    }
+
+    // Sets the footprint from the width in world units of the patch the ray stands for
+    pub fn set_footprint(&mut self, width: f64) {
+        self.footprint = width * self.uv_per_unit;
+    }
 }
 
 pub trait Hittable: Send + Sync + Debug {
@@ -42,6 +85,41 @@ pub trait Hittable: Send + Sync + Debug {
     // that are further than the closest object hit.
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self) -> AABB;
+
+    // Solid angle probability density of random() returning the given direction from origin.
+    // Only shapes that can be used as lights need to implement this and random()
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.
+    }
+
+    // A random direction from origin towards a point on the surface of the object
+    fn random(&self, _origin: &Point3) -> Vec3 {
+        Vec3::new(1., 0., 0.)
+    }
+
+    // A random point on the surface and the area density of picking it, used to start light
+    // paths. The record holds the outward normal. None for shapes that can't be sampled
+    fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        None
+    }
+
+    // Area density of sample_surface() returning point, where point is found by tracing from
+    // origin. Zero if the segment from origin doesn't end on the surface of this object
+    fn surface_pdf(&self, _origin: &Point3, _point: &Point3) -> f64 {
+        0.
+    }
+}
+
+// Intersect the segment from origin to point with an object, only accepting a hit at the point
+// itself. Lets shapes find out whether a point lies on them with their own hit()
+pub fn hit_segment_end(
+    object: &dyn Hittable,
+    origin: &Point3,
+    point: &Point3,
+    rec: &mut HitRecord,
+) -> bool {
+    let r = Ray::new(*origin, *point - *origin);
+    object.hit(&r, Interval::new(1. - 1e-6, 1. + 1e-6), rec)
 }
 
 #[derive(Debug)]
@@ -90,12 +168,54 @@ impl Hittable for HittableList {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    // Every object is picked with the same probability, so the density is the average
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|obj| obj.pdf_value(origin, direction))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1., 0., 0.);
+        }
+        let index = random_int(0, self.objects.len() as i32 - 1) as usize;
+        self.objects[index.min(self.objects.len() - 1)].random(origin)
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        if self.objects.is_empty() {
+            return None;
+        }
+        let index = random_int(0, self.objects.len() as i32 - 1) as usize;
+        let (rec, pdf) = self.objects[index.min(self.objects.len() - 1)].sample_surface()?;
+        Some((rec, pdf / self.objects.len() as f64))
+    }
+
+    fn surface_pdf(&self, origin: &Point3, point: &Point3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|obj| obj.surface_pdf(origin, point))
+            .sum();
+        sum / self.objects.len() as f64
+    }
 }
 
 pub struct HittableAxisCompare(Arc<dyn Hittable>);
 
 impl HittableAxisCompare {
-    fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis_index: i32) -> Ordering {
+    pub fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis_index: i32) -> Ordering {
         let a_bb = a.bounding_box();
         let b_bb = b.bounding_box();
         let a_axis_interval = a_bb.axis_interval(axis_index);
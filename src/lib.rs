@@ -1,14 +1,35 @@
 pub mod aabb;
+pub mod alpha_mask;
+pub mod background;
+pub mod bdpt;
 pub mod bvh;
 pub mod camera;
 pub mod color;
+pub mod constant_medium;
+pub mod environment;
 pub mod hittable;
 pub mod interval;
+pub mod linear_bvh;
 pub mod material;
+pub mod matrix;
+pub mod mesh;
+pub mod microfacet;
+pub mod mipmap;
+pub mod normal_map;
+pub mod obj;
+pub mod onb;
+pub mod output;
+pub mod pdf;
 pub mod perlin;
+pub mod principled;
+pub mod procedural;
+pub mod quad;
 pub mod ray;
 pub mod scene;
 pub mod sphere;
 pub mod texture;
+pub mod texture_nodes;
+pub mod transform;
+pub mod triangle;
 pub mod utils;
 pub mod vec3;
@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    bvh::{sah_split, SAH_MAX_LEAF_OBJECTS},
+    hittable::{HitRecord, Hittable, HittableList},
+    interval::Interval,
+    ray::Ray,
+};
+
+// Size of the traversal stack. Traversal never holds more than one entry per level plus one, so
+// the builder stops splitting before trees get deeper than this
+const MAX_TRAVERSAL_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct LinearNode {
+    bbox: AABB,
+    // For a leaf, the first primitive of its range. For an interior node, the index of the
+    // right child, the left child always directly follows its parent in the node list
+    offset: usize,
+    // Number of primitives in a leaf, zero for interior nodes
+    count: usize,
+    // Axis the children were split on, used to visit the nearer child first
+    axis: usize,
+}
+
+/// A BVH stored as a flat list of nodes that refer to their children and primitives by index.
+/// It only knows the bounding boxes of the primitives, the owner keeps the primitives themselves
+/// in the order returned by build() and tests them in the callback given to hit().
+#[derive(Debug, Default)]
+pub struct BVHIndex {
+    nodes: Vec<LinearNode>,
+}
+
+impl BVHIndex {
+    /// Build the tree with the binned SAH split over the given primitive bounding boxes.
+    /// Returns the tree and the order the primitives must be stored in, so that every leaf
+    /// refers to a contiguous range of them.
+    pub fn build(boxes: &[AABB]) -> (Self, Vec<usize>) {
+        let mut order: Vec<usize> = (0..boxes.len()).collect();
+        let mut bvh = Self::default();
+        if !boxes.is_empty() {
+            bvh.construct(boxes, &mut order, 0, 0);
+        }
+        (bvh, order)
+    }
+
+    // Builds the subtree for the primitives order[start..start + order.len()], returns the
+    // index of its root node
+    fn construct(
+        &mut self,
+        boxes: &[AABB],
+        order: &mut [usize],
+        start: usize,
+        depth: usize,
+    ) -> usize {
+        let mut bbox = AABB::empty();
+        for &i in order.iter() {
+            bbox = AABB::with_boxes(&bbox, &boxes[i]);
+        }
+        let node_index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            bbox,
+            offset: start,
+            count: order.len(),
+            axis: 0,
+        });
+        if order.len() == 1 || depth + 2 >= MAX_TRAVERSAL_DEPTH {
+            return node_index;
+        }
+
+        let (mid, axis) = match sah_split(order, &bbox, |&i| boxes[i]) {
+            Some(split) => split,
+            None if order.len() <= SAH_MAX_LEAF_OBJECTS => return node_index,
+            // No useful split but too many primitives for a leaf, cut the range in half
+            None => (order.len() / 2, bbox.longest_axis() as usize),
+        };
+
+        let (left, right) = order.split_at_mut(mid);
+        self.construct(boxes, left, start, depth + 1);
+        let right_index = self.construct(boxes, right, start + mid, depth + 1);
+        self.nodes[node_index] = LinearNode {
+            bbox,
+            offset: right_index,
+            count: 0,
+            axis,
+        };
+        node_index
+    }
+
+    pub fn bounding_box(&self) -> AABB {
+        self.nodes.first().map_or(AABB::empty(), |root| root.bbox)
+    }
+
+    /// Find the closest hit along the ray, calling hit_primitive with the index of every
+    /// primitive whose leaf the ray reaches. The callback must only report hits inside the
+    /// interval it is given and record them in the hit record, like Hittable::hit.
+    pub fn hit(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        rec: &mut HitRecord,
+        mut hit_primitive: impl FnMut(usize, &Ray, Interval, &mut HitRecord) -> bool,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let direction = r.direction();
+        let mut hit_anything = false;
+        let mut closest_so_far = ray_t.max;
+
+        let mut stack = [0usize; MAX_TRAVERSAL_DEPTH];
+        let mut stack_size = 1;
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node_index = stack[stack_size];
+            let node = &self.nodes[node_index];
+            if !node.bbox.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for i in node.offset..node.offset + node.count {
+                    let interval = Interval::new(ray_t.min, closest_so_far);
+                    if hit_primitive(i, r, interval, rec) {
+                        hit_anything = true;
+                        closest_so_far = rec.t;
+                    }
+                }
+            } else {
+                // Push the far child first so the near child is popped next. Once the near
+                // side has a hit, the far side can often be culled by its box alone
+                let (near, far) = if direction[node.axis] < 0. {
+                    (node.offset, node_index + 1)
+                } else {
+                    (node_index + 1, node.offset)
+                };
+                stack[stack_size] = far;
+                stack[stack_size + 1] = near;
+                stack_size += 2;
+            }
+        }
+        hit_anything
+    }
+}
+
+/// A flattened BVH over arbitrary hittables, a drop-in alternative to BVHNode with the nodes
+/// in one contiguous list and the objects stored in leaf order.
+#[derive(Debug)]
+pub struct LinearBVH {
+    bvh: BVHIndex,
+    objects: Vec<Arc<dyn Hittable>>,
+}
+
+impl LinearBVH {
+    pub fn new(list: &HittableList) -> Arc<Self> {
+        let boxes: Vec<AABB> = list.objects.iter().map(|o| o.bounding_box()).collect();
+        let (bvh, order) = BVHIndex::build(&boxes);
+        let objects = order.iter().map(|&i| list.objects[i].clone()).collect();
+        Arc::new(Self { bvh, objects })
+    }
+}
+
+impl Hittable for LinearBVH {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        self.bvh.hit(r, ray_t, rec, |i, r, interval, rec| {
+            self.objects[i].hit(r, interval, rec)
+        })
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bvh.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color, material::Lambertian, ray::Point3, sphere::Sphere,
+        utils::random_double_range, vec3::Vec3,
+    };
+
+    #[test]
+    fn matches_brute_force() {
+        let mut world = HittableList::new();
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        for _ in 0..500 {
+            let center = Point3::random_range(-10., 10.);
+            let radius = random_double_range(0.05, 1.);
+            world.add(Arc::new(Sphere::new(center, radius, material.clone())));
+        }
+        let bvh = LinearBVH::new(&world);
+
+        for _ in 0..500 {
+            let r = Ray::new(Point3::random_range(-12., 12.), Vec3::random_unit_vector());
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+            let mut expected = HitRecord::default();
+            let hit_expected = world.hit(&r, ray_t, &mut expected);
+
+            let mut rec = HitRecord::default();
+            assert_eq!(bvh.hit(&r, ray_t, &mut rec), hit_expected);
+            if hit_expected {
+                assert_eq!(rec.t, expected.t);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_list_never_hits() {
+        let bvh = LinearBVH::new(&HittableList::new());
+        let r = Ray::new(Point3::new(0., 0., 0.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(!bvh.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+    }
+}
@@ -1,38 +1,62 @@
-use std::{f64::consts, fs::File, io::Write, sync::Arc};
+use std::{f64::consts, path::Path, sync::Arc};
 
 use rrtm::{
+    background::Background,
     bvh::BVHNode,
     camera::Camera,
     color::Color,
     hittable::Hittable,
     hittable::HittableList,
     material::*,
+    matrix::Mat4,
+    mipmap::WrapMode,
+    normal_map::NormalMapped,
+    output::{is_supported, write_image, OutputError},
+    principled::Principled,
+    procedural::{ColorRamp, Fbm, Gradient, Stripes, Wood, Worley, WorleyFeature},
+    quad::{make_box, Quad},
     ray::Point3,
     sphere::Sphere,
-    texture::{CheckerTexture, ImageTexture, NoiseTexture},
+    texture::{CheckerTexture, ColorSpace, ImageTexture, NoiseTexture, Texture},
+    texture_nodes::{Mix, Remap, Triplanar, UvTransform},
+    transform::Transform,
+    triangle::Triangle,
     utils::{random_double, random_double_range},
     vec3::Vec3,
 };
 
+// Usage: rrtm [output file], the format following the extension, see output.rs
 fn main() {
     use std::time::Instant;
     let now = Instant::now();
-    let out = std::io::stdout();
+    let output = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "image.png".to_string());
+    if !is_supported(Path::new(&output)) {
+        eprintln!("ERROR: {}", OutputError::UnsupportedFormat(output.into()));
+        std::process::exit(1);
+    }
 
     let (camera, world) = perlin();
     let pixels = camera.render(&world);
-    let _ = writeln!(
-        &out,
-        "P3\n{} {}\n255\n",
-        camera.image_width, camera.image_height
-    );
-    for p in pixels {
-        let _ = writeln!(&out, "{}", p.get_string());
+    let (width, height) = (camera.image_width(), camera.image_height());
+    if let Err(e) = write_image(Path::new(&output), &pixels, width, height) {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
     }
     let elapsed = now.elapsed();
     dbg!(elapsed);
 }
 
+// One of the images in the textures directory of the repository. The demo scenes are useless
+// without them, so a missing one stops the program
+fn texture(filename: &str, color_space: ColorSpace) -> ImageTexture {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("textures")
+        .join(filename);
+    ImageTexture::open(&path, color_space).unwrap_or_else(|e| panic!("{}", e))
+}
+
 pub fn perlin() -> (Camera, Arc<dyn Hittable>) {
     let lookfrom = Point3::new(13., 2., 3.);
     let lookat = Point3::new(0., 0., 0.);
@@ -40,7 +64,7 @@ pub fn perlin() -> (Camera, Arc<dyn Hittable>) {
     let camera = Camera::new(400, 16. / 9., 100, 50, 20., lookfrom, lookat, vup, 0., 12.);
 
     let mut world = HittableList::new();
-    let pertext = Arc::new(NoiseTexture::new());
+    let pertext = Arc::new(NoiseTexture::marble(4.));
     let ground = Arc::new(Sphere::new(
         Point3::new(0., -1000., 0.),
         1000.,
@@ -55,6 +79,170 @@ pub fn perlin() -> (Camera, Arc<dyn Hittable>) {
     world.add(sphere);
     (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
 }
+fn quads() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 0., 9.);
+    let lookat = Point3::new(0., 0., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let camera = Camera::new(400, 1., 100, 50, 80., lookfrom, lookat, vup, 0., 10.);
+
+    let mut world = HittableList::new();
+
+    let left_red = Arc::new(Lambertian::new(Color::new(1.0, 0.2, 0.2)));
+    let back_green = Arc::new(Lambertian::new(Color::new(0.2, 1.0, 0.2)));
+    let right_blue = Arc::new(Lambertian::new(Color::new(0.2, 0.2, 1.0)));
+    let upper_orange = Arc::new(Lambertian::new(Color::new(1.0, 0.5, 0.0)));
+    let lower_teal = Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.8)));
+
+    world.add(Arc::new(Quad::new(
+        Point3::new(-3., -2., 5.),
+        Vec3::new(0., 0., -4.),
+        Vec3::new(0., 4., 0.),
+        left_red,
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(-2., -2., 0.),
+        Vec3::new(4., 0., 0.),
+        Vec3::new(0., 4., 0.),
+        back_green,
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(3., -2., 1.),
+        Vec3::new(0., 0., 4.),
+        Vec3::new(0., 4., 0.),
+        right_blue,
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(-2., 3., 1.),
+        Vec3::new(4., 0., 0.),
+        Vec3::new(0., 0., 4.),
+        upper_orange,
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(-2., -3., 5.),
+        Vec3::new(4., 0., 0.),
+        Vec3::new(0., 0., -4.),
+        lower_teal,
+    )));
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+fn simple_light() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(26., 3., 6.);
+    let lookat = Point3::new(0., 2., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let mut camera = Camera::new(400, 16. / 9., 100, 50, 20., lookfrom, lookat, vup, 0., 10.);
+    camera.background = Background::Solid(Color::default());
+
+    let mut world = HittableList::new();
+    let pertext = Arc::new(NoiseTexture::marble(4.));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::with_texture(pertext.clone())),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., 2., 0.),
+        2.,
+        Arc::new(Lambertian::with_texture(pertext)),
+    )));
+
+    let difflight = Arc::new(DiffuseLight::new(Color::new(4., 4., 4.)));
+    let mut lights = HittableList::new();
+    lights.add(Arc::new(Sphere::new(
+        Point3::new(0., 7., 0.),
+        2.,
+        difflight.clone(),
+    )));
+    lights.add(Arc::new(Quad::new(
+        Point3::new(3., 1., -2.),
+        Vec3::new(2., 0., 0.),
+        Vec3::new(0., 2., 0.),
+        difflight,
+    )));
+    for light in &lights.objects {
+        world.add(light.clone());
+    }
+    camera.lights = Some(Arc::new(lights));
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
+fn cornell_box() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(278., 278., -800.);
+    let lookat = Point3::new(278., 278., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let mut camera = Camera::new(600, 1., 200, 50, 40., lookfrom, lookat, vup, 0., 10.);
+    camera.background = Background::Solid(Color::default());
+
+    let mut world = HittableList::new();
+    let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new(Color::new(15., 15., 15.)));
+
+    world.add(Arc::new(Quad::new(
+        Point3::new(555., 0., 0.),
+        Vec3::new(0., 555., 0.),
+        Vec3::new(0., 0., 555.),
+        green,
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(0., 0., 0.),
+        Vec3::new(0., 555., 0.),
+        Vec3::new(0., 0., 555.),
+        red,
+    )));
+    let light = Arc::new(Quad::new(
+        Point3::new(343., 554., 332.),
+        Vec3::new(-130., 0., 0.),
+        Vec3::new(0., 0., -105.),
+        light,
+    ));
+    world.add(light.clone());
+    camera.lights = Some(light);
+    world.add(Arc::new(Quad::new(
+        Point3::new(0., 0., 0.),
+        Vec3::new(555., 0., 0.),
+        Vec3::new(0., 0., 555.),
+        white.clone(),
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(555., 555., 555.),
+        Vec3::new(-555., 0., 0.),
+        Vec3::new(0., 0., -555.),
+        white.clone(),
+    )));
+    world.add(Arc::new(Quad::new(
+        Point3::new(0., 0., 555.),
+        Vec3::new(555., 0., 0.),
+        Vec3::new(0., 555., 0.),
+        white.clone(),
+    )));
+
+    let box1 = make_box(
+        &Point3::new(0., 0., 0.),
+        &Point3::new(165., 330., 165.),
+        white.clone(),
+    );
+    let box1 = Arc::new(Transform::rotate_y(box1, 15.));
+    world.add(Arc::new(Transform::translate(
+        box1,
+        Vec3::new(265., 0., 295.),
+    )));
+
+    let box2 = make_box(
+        &Point3::new(0., 0., 0.),
+        &Point3::new(165., 165., 165.),
+        white,
+    );
+    world.add(Arc::new(Transform::new(
+        box2,
+        Mat4::translation(Vec3::new(130., 0., 65.)) * Mat4::rotation_y(-18.),
+    )));
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
 fn mike() -> (Camera, Arc<dyn Hittable>) {
     let lookfrom = Point3::new(0., 0., 12.);
     let lookat = Point3::new(0., 0., 0.);
@@ -62,13 +250,314 @@ fn mike() -> (Camera, Arc<dyn Hittable>) {
     let camera = Camera::new(400, 16. / 9., 100, 50, 20., lookfrom, lookat, vup, 0., 12.);
 
     let mut world = HittableList::new();
-    let earth_texture = Arc::new(ImageTexture::new("cat.jpg"));
+    let earth_texture = Arc::new(texture("cat.jpg", ColorSpace::Srgb));
     let earth_surface = Arc::new(Lambertian::with_texture(earth_texture));
     let globe = Arc::new(Sphere::new(Point3::new(0., 0., 0.), 2., earth_surface));
     world.add(globe);
 
     (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
 }
+fn conductors() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 2., 10.);
+    let lookat = Point3::new(0., 1., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let camera = Camera::new(400, 16. / 9., 100, 50, 30., lookfrom, lookat, vup, 0., 10.);
+
+    let mut world = HittableList::new();
+    let checker = Arc::new(CheckerTexture::with_color(
+        0.5,
+        &Color::new(0.2, 0.3, 0.1),
+        &Color::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::with_texture(checker)),
+    )));
+
+    world.add(Arc::new(Sphere::new(
+        Point3::new(-2.5, 1., 0.),
+        1.,
+        Arc::new(Conductor::gold(0.2)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., 1., 0.),
+        1.,
+        Arc::new(Conductor::copper(0.4)),
+    )));
+    // Brushed: much rougher along one tangent direction than the other
+    world.add(Arc::new(Sphere::new(
+        Point3::new(2.5, 1., 0.),
+        1.,
+        Arc::new(Conductor::anisotropic(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            0.1,
+            0.5,
+        )),
+    )));
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
+fn frosted_glass() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 2., 10.);
+    let lookat = Point3::new(0., 1., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let camera = Camera::new(400, 16. / 9., 100, 50, 30., lookfrom, lookat, vup, 0., 10.);
+
+    let mut world = HittableList::new();
+    let checker = Arc::new(CheckerTexture::with_color(
+        0.5,
+        &Color::new(0.2, 0.3, 0.1),
+        &Color::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::with_texture(checker)),
+    )));
+
+    world.add(Arc::new(Sphere::new(
+        Point3::new(-2.5, 1., 0.),
+        1.,
+        Arc::new(RoughDielectric::new(1.5, 0.)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., 1., 0.),
+        1.,
+        Arc::new(RoughDielectric::new(1.5, 0.3)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(2.5, 1., 0.),
+        1.,
+        Arc::new(RoughDielectric::with_absorption(
+            1.5,
+            0.1,
+            Color::new(0.4, 0.8, 0.5),
+        )),
+    )));
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
+fn principled() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 2., 12.);
+    let lookat = Point3::new(0., 1., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let camera = Camera::new(400, 16. / 9., 100, 50, 30., lookfrom, lookat, vup, 0., 12.);
+
+    let mut world = HittableList::new();
+    let checker = Arc::new(CheckerTexture::with_color(
+        0.5,
+        &Color::new(0.2, 0.3, 0.1),
+        &Color::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Principled::with_texture(checker)),
+    )));
+
+    let plastic = Principled::new(Color::new(0.8, 0.1, 0.1));
+    let gold = Principled::metal(Color::new(1., 0.78, 0.34), 0.3);
+    let glass = Principled::glass(Color::new(0.9, 0.95, 1.), 0.1);
+    let mut car_paint = Principled::new(Color::new(0.05, 0.15, 0.5));
+    car_paint.metallic = Principled::constant(0.5);
+    car_paint.clearcoat = Principled::constant(1.);
+    let mut velvet = Principled::new(Color::new(0.4, 0.1, 0.3));
+    velvet.roughness = Principled::constant(1.);
+    velvet.sheen = Principled::constant(1.);
+
+    for (i, material) in [plastic, gold, glass, car_paint, velvet]
+        .into_iter()
+        .enumerate()
+    {
+        world.add(Arc::new(Sphere::new(
+            Point3::new(-4.4 + 2.2 * i as f64, 1., 0.),
+            1.,
+            Arc::new(material),
+        )));
+    }
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
+fn bump_mapping() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 2., 10.);
+    let lookat = Point3::new(0., 1., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let mut camera = Camera::new(400, 16. / 9., 100, 50, 30., lookfrom, lookat, vup, 0., 10.);
+    camera.background = Background::Solid(Color::new(0.02, 0.02, 0.03));
+
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+
+    // The same map as color and, read as is, as height, so land stands out from the sea. A low
+    // light from the side brings out the relief
+    let earth_texture = Arc::new(texture("earthmap.jpg", ColorSpace::Srgb));
+    let earth_height = Arc::new(texture("earthmap.jpg", ColorSpace::Linear));
+    let earth_surface = Arc::new(Lambertian::with_texture(earth_texture));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(-1.5, 1., 0.),
+        1.,
+        earth_surface.clone(),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(1.5, 1., 0.),
+        1.,
+        Arc::new(NormalMapped::bump(earth_surface, earth_height, 0.01)),
+    )));
+
+    let light = Arc::new(Sphere::new(
+        Point3::new(8., 3., 4.),
+        1.,
+        Arc::new(DiffuseLight::new(Color::new(30., 30., 30.))),
+    ));
+    world.add(light.clone());
+    camera.lights = Some(light);
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
+fn procedural_textures() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 3., 12.);
+    let lookat = Point3::new(0., 1., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let camera = Camera::new(400, 16. / 9., 100, 50, 30., lookfrom, lookat, vup, 0., 12.);
+
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::with_texture(Arc::new(Stripes::with_color(
+            Vec3::new(1., 0., 1.),
+            &Color::new(0.8, 0.8, 0.8),
+            &Color::new(0.3, 0.3, 0.35),
+        )))),
+    )));
+
+    let cells = ColorRamp::new(
+        Arc::new(Worley::new(4., WorleyFeature::F2MinusF1)),
+        vec![
+            (0., Color::new(0.05, 0.05, 0.05)),
+            (0.15, Color::new(0.9, 0.6, 0.2)),
+        ],
+    );
+    let clouds = ColorRamp::new(
+        Arc::new(Fbm::new(2., 6)),
+        vec![
+            (0.3, Color::new(0.1, 0.3, 0.8)),
+            (0.7, Color::new(0.95, 0.95, 0.95)),
+        ],
+    );
+    let wood = Wood::with_color(6., &Color::new(0.75, 0.5, 0.3), &Color::new(0.4, 0.2, 0.1));
+    let textures: [Arc<dyn Texture>; 3] = [Arc::new(cells), Arc::new(clouds), Arc::new(wood)];
+    for (i, texture) in textures.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Point3::new(2.5 * (i as f64 - 1.), 1., 0.),
+            1.,
+            Arc::new(Lambertian::with_texture(texture)),
+        )));
+    }
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
+fn texture_graph() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 3., 12.);
+    let lookat = Point3::new(0., 1., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+    let camera = Camera::new(400, 16. / 9., 100, 50, 30., lookfrom, lookat, vup, 0., 12.);
+
+    let mut world = HittableList::new();
+    // Floor of small repeated cat tiles
+    let mut tiles = texture("cat.jpg", ColorSpace::Srgb);
+    tiles.wrap = WrapMode::Repeat;
+    world.add(Arc::new(Quad::new(
+        Point3::new(-20., 0., -20.),
+        Vec3::new(40., 0., 0.),
+        Vec3::new(0., 0., 40.),
+        Arc::new(Lambertian::with_texture(Arc::new(UvTransform::new(
+            Arc::new(tiles),
+            [20., 20.],
+            45.,
+            [0., 0.],
+        )))),
+    )));
+
+    // Cells projected onto a box, which has no uvs across its faces
+    let cells = Arc::new(Remap::new(
+        Arc::new(Worley::new(3., WorleyFeature::F2MinusF1)),
+        0.,
+        0.1,
+        Color::new(0.1, 0.1, 0.1),
+        Color::new(0.2, 0.6, 0.3),
+    ));
+    let block = make_box(
+        &Point3::new(-3., 0., -1.),
+        &Point3::new(-1., 2., 1.),
+        Arc::new(Lambertian::with_texture(Arc::new(Triplanar::new(
+            cells, 1.,
+        )))),
+    );
+    world.add(Arc::new(Transform::rotate_y(block, 30.)));
+
+    // Earth fading into marble from the bottom up
+    let globe = Mix::new(
+        Arc::new(texture("earthmap.jpg", ColorSpace::Srgb)),
+        Arc::new(NoiseTexture::marble(4.)),
+        Arc::new(Gradient::new(
+            Point3::new(0., 0., 0.),
+            Vec3::new(0., 2., 0.),
+        )),
+    );
+    world.add(Arc::new(Sphere::new(
+        Point3::new(2., 1., 0.),
+        1.,
+        Arc::new(Lambertian::with_texture(Arc::new(globe))),
+    )));
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
+fn tiled_floor() -> (Camera, Arc<dyn Hittable>) {
+    let lookfrom = Point3::new(0., 1., 0.);
+    let lookat = Point3::new(0., 0.5, -10.);
+    let vup = Vec3::new(0., 1., 0.);
+    let camera = Camera::new(400, 16. / 9., 50, 10, 60., lookfrom, lookat, vup, 0., 10.);
+
+    // The image is repeated 50 times in both directions, mirrored so the tiles line up. Far
+    // away tiles are read from the smaller MIP levels instead of shimmering
+    let mut cat = texture("cat.jpg", ColorSpace::Srgb);
+    cat.wrap = WrapMode::Mirror;
+    let floor = Arc::new(Lambertian::with_texture(Arc::new(cat)));
+    let (size, tiles) = (100., 50.);
+    let corners = [
+        Point3::new(-size, 0., size),
+        Point3::new(size, 0., size),
+        Point3::new(size, 0., -size),
+        Point3::new(-size, 0., -size),
+    ];
+    let uvs = [[0., 0.], [tiles, 0.], [tiles, tiles], [0., tiles]];
+
+    let mut world = HittableList::new();
+    for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+        world.add(Arc::new(Triangle::with_attributes(
+            [corners[a], corners[b], corners[c]],
+            None,
+            Some([uvs[a], uvs[b], uvs[c]]),
+            floor.clone(),
+        )));
+    }
+
+    (camera, BVHNode::new(&mut world) as Arc<dyn Hittable>)
+}
+
 fn earth() -> (Camera, Arc<dyn Hittable>) {
     let lookfrom = Point3::new(0., 0., 12.);
     let lookat = Point3::new(0., 0., 0.);
@@ -76,7 +565,7 @@ fn earth() -> (Camera, Arc<dyn Hittable>) {
     let camera = Camera::new(400, 16. / 9., 100, 50, 20., lookfrom, lookat, vup, 0., 12.);
 
     let mut world = HittableList::new();
-    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg"));
+    let earth_texture = Arc::new(texture("earthmap.jpg", ColorSpace::Srgb));
     let earth_surface = Arc::new(Lambertian::with_texture(earth_texture));
     let globe = Arc::new(Sphere::new(Point3::new(0., 0., 0.), 2., earth_surface));
     world.add(globe);
@@ -1,22 +1,75 @@
-use std::sync::Arc;
+use std::{f64::consts::PI, sync::Arc};
 
 use crate::{
     color::Color,
     hittable::HitRecord,
-    ray::Ray,
+    microfacet::{
+        dielectric_eval, dielectric_pdf, fresnel_conductor, fresnel_dielectric, reflection_eval,
+        reflection_pdf, refract, shading_frame, DielectricPdf, MicrofacetReflectionPdf,
+        TrowbridgeReitz,
+    },
+    pdf::{CosinePdf, Pdf, SpherePdf},
+    ray::{Point3, Ray},
     texture::{SolidColor, Texture},
     utils::random_double,
     vec3::{dot, unit_vector, Vec3},
 };
 
+// How a material responds to a ray that hits it. Exactly one of specular_ray and pdf is set
+pub struct ScatterRecord {
+    // Color the scattered light is multiplied by. For materials with a pdf integrators weight
+    // each sampled direction by eval() / pdf() instead, which this only approximates unless
+    // the pdf matches the material exactly
+    pub attenuation: Color,
+    // The single direction a mirror or glass-like material scatters into. It has no density, so
+    // integrators follow it as is instead of sampling or evaluating the material
+    pub specular_ray: Option<Ray>,
+    // Distribution of the directions a rough material scatters into
+    pub pdf: Option<Arc<dyn Pdf>>,
+}
+
+impl ScatterRecord {
+    pub fn specular(attenuation: Color, ray: Ray) -> Self {
+        Self {
+            attenuation,
+            specular_ray: Some(ray),
+            pdf: None,
+        }
+    }
+
+    pub fn with_pdf(attenuation: Color, pdf: Arc<dyn Pdf>) -> Self {
+        Self {
+            attenuation,
+            specular_ray: None,
+            pdf: Some(pdf),
+        }
+    }
+}
+
 pub trait Material: Send + Sync + std::fmt::Debug {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool;
+    // None if the material absorbs the ray, which is also what light sources do
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    // Light scattered towards scattered for each unit of light arriving along r_in, with the
+    // cosine at the surface included (the BSDF times cos(theta)). Lets integrators weight
+    // directions that were sampled by something other than the material, such as a light.
+    // Specular materials return zero, no other direction can ever match theirs
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color {
+        Color::default()
+    }
+
+    // Solid angle probability density of scatter() picking the direction of scattered
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.
+    }
+
+    // Light given off by the surface itself, independent of any incoming ray.
+    // Most materials don't emit anything
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        Color::default()
+    }
 }
 
 #[derive(Debug)]
@@ -37,23 +90,23 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
-        if scatter_direction.near_zero() {
-            // Catch degenerate scatter direction where the random_unit_vector is the exact
-            // opposite of the normal, thus producing a 0-vector scatter and can lead to undefined
-            // behaviors
-            scatter_direction = rec.normal
-        }
-        *scattered = Ray::new_tm(rec.p, scatter_direction, r_in.time());
-        *attenuation = self.tex.value(rec.u, rec.v, &rec.p);
-        return true;
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let attenuation = self.tex.sample(rec);
+        Some(ScatterRecord::with_pdf(
+            attenuation,
+            Arc::new(CosinePdf::new(&rec.normal)),
+        ))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.tex.sample(rec) * self.pdf(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        // Cosine weighted, which is exactly what the albedo / pi * cos(theta) of a diffuse
+        // surface calls for
+        let cos_theta = dot(rec.normal, unit_vector(&scattered.direction()));
+        f64::max(0., cos_theta / PI)
     }
 }
 
@@ -70,18 +123,140 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut reflected = Vec3::reflect(&r_in.direction(), &rec.normal);
         reflected = unit_vector(&reflected) + Vec3::random_unit_vector() * self.fuzz;
-        *scattered = Ray::new_tm(rec.p, reflected, r_in.time());
-        *attenuation = self.albedo;
-        return dot(scattered.direction(), rec.normal) > 0.;
+        // Fuzzed reflections that end up below the surface are absorbed
+        if dot(reflected, rec.normal) <= 0. {
+            return None;
+        }
+        let scattered = Ray::new_tm(rec.p, reflected, r_in.time());
+        Some(ScatterRecord::specular(self.albedo, scattered))
+    }
+}
+
+// Rough metal with GGX microfacets. Its color comes from the complex index of refraction
+// eta + i k per channel, and the roughness along the two tangent directions can be driven by any
+// texture (the first channel is used)
+#[derive(Debug)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    roughness_u: Arc<dyn Texture>,
+    roughness_v: Arc<dyn Texture>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self::anisotropic(eta, k, roughness, roughness)
+    }
+
+    pub fn anisotropic(eta: Color, k: Color, roughness_u: f64, roughness_v: f64) -> Self {
+        Self::with_textures(
+            eta,
+            k,
+            Arc::new(SolidColor::new(Color::new(
+                roughness_u,
+                roughness_u,
+                roughness_u,
+            ))),
+            Arc::new(SolidColor::new(Color::new(
+                roughness_v,
+                roughness_v,
+                roughness_v,
+            ))),
+        )
+    }
+
+    pub fn with_textures(
+        eta: Color,
+        k: Color,
+        roughness_u: Arc<dyn Texture>,
+        roughness_v: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            eta,
+            k,
+            roughness_u,
+            roughness_v,
+        }
+    }
+
+    // Measured indices of refraction at the red, green and blue wavelengths
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    fn distribution(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(
+            self.roughness_u.sample(rec).x(),
+            self.roughness_v.sample(rec).x(),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = shading_frame(rec);
+        let wo = frame.to_local(&-unit_vector(&r_in.direction()));
+        if wo.z() <= 0. {
+            return None;
+        }
+        let attenuation = fresnel_conductor(wo.z(), &self.eta, &self.k);
+        let distribution = self.distribution(rec);
+        if distribution.is_smooth() {
+            let reflected = Vec3::reflect(&r_in.direction(), &rec.normal);
+            let scattered = Ray::new_tm(rec.p, reflected, r_in.time());
+            return Some(ScatterRecord::specular(attenuation, scattered));
+        }
+        Some(ScatterRecord::with_pdf(
+            attenuation,
+            Arc::new(MicrofacetReflectionPdf::new(frame, wo, distribution)),
+        ))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let frame = shading_frame(rec);
+        let wo = frame.to_local(&-unit_vector(&r_in.direction()));
+        let wi = frame.to_local(&unit_vector(&scattered.direction()));
+        let distribution = self.distribution(rec);
+        if wo.z() <= 0. || wi.z() <= 0. || distribution.is_smooth() {
+            return Color::default();
+        }
+        let wm = unit_vector(&(wo + wi));
+        fresnel_conductor(dot(wo, wm), &self.eta, &self.k)
+            * reflection_eval(&distribution, &wo, &wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = shading_frame(rec);
+        let wo = frame.to_local(&-unit_vector(&r_in.direction()));
+        let wi = frame.to_local(&unit_vector(&scattered.direction()));
+        let distribution = self.distribution(rec);
+        if distribution.is_smooth() {
+            return 0.;
+        }
+        reflection_pdf(&distribution, &wo, &wi)
     }
 }
 
@@ -107,14 +282,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let ri = if rec.front_face {
             1.0 / self.refraction_index
         } else {
@@ -125,15 +293,191 @@ impl Material for Dielectric {
         let cos_theta = f64::min(dot(-unit_direction, rec.normal), 1.0);
         let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction: Vec3;
-        if cannot_refract || Dielectric::reflectance(cos_theta, ri) > random_double() {
-            // Must reflect
-            direction = Vec3::reflect(&unit_direction, &rec.normal)
+        let direction =
+            if cannot_refract || Dielectric::reflectance(cos_theta, ri) > random_double() {
+                // Must reflect
+                Vec3::reflect(&unit_direction, &rec.normal)
+            } else {
+                // Must refract
+                Vec3::refract(&unit_direction, &rec.normal, ri)
+            };
+        let scattered = Ray::new_tm(rec.p, direction, r_in.time());
+        Some(ScatterRecord::specular(
+            Color::new(1.0, 1.0, 1.0),
+            scattered,
+        ))
+    }
+}
+
+// Frosted glass: a dielectric with GGX microfacets and exact Fresnel. The optional absorption is
+// the color light takes on after travelling a unit distance inside, for tinted glass following
+// the Beer-Lambert law. Roughness 0 gives smooth glass
+#[derive(Debug)]
+pub struct RoughDielectric {
+    refraction_index: f64,
+    roughness: Arc<dyn Texture>,
+    absorption: Option<Color>,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64) -> Self {
+        Self::with_texture(
+            refraction_index,
+            Arc::new(SolidColor::new(Color::new(roughness, roughness, roughness))),
+        )
+    }
+
+    pub fn with_texture(refraction_index: f64, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            refraction_index,
+            roughness,
+            absorption: None,
+        }
+    }
+
+    pub fn with_absorption(refraction_index: f64, roughness: f64, absorption: Color) -> Self {
+        Self {
+            absorption: Some(absorption),
+            ..Self::new(refraction_index, roughness)
+        }
+    }
+
+    fn distribution(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        let roughness = self.roughness.sample(rec).x();
+        TrowbridgeReitz::from_roughness(roughness, roughness)
+    }
+
+    // Index of refraction below the shading frame over the one above it. The normal always faces
+    // the incoming ray, so above is outside only when the ray hit the front face
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {
+            self.refraction_index
+        } else {
+            1. / self.refraction_index
+        }
+    }
+
+    // Light surviving the way here. Rays hitting the back face travelled inside the glass
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        match self.absorption {
+            Some(absorption) if !rec.front_face => {
+                let distance = rec.t * r_in.direction().length();
+                Color::new(
+                    absorption.x().powf(distance),
+                    absorption.y().powf(distance),
+                    absorption.z().powf(distance),
+                )
+            }
+            _ => Color::new(1., 1., 1.),
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let attenuation = self.transmittance(r_in, rec);
+        let frame = shading_frame(rec);
+        let wo = frame.to_local(&-unit_vector(&r_in.direction()));
+        let eta = self.eta(rec);
+        let distribution = self.distribution(rec);
+        if !distribution.is_smooth() {
+            return Some(ScatterRecord::with_pdf(
+                attenuation,
+                Arc::new(DielectricPdf::new(frame, wo, eta, distribution)),
+            ));
+        }
+
+        // Smooth glass, reflect or refract in proportion to the exact Fresnel reflectance
+        let unit_direction = unit_vector(&r_in.direction());
+        let reflected = Vec3::reflect(&unit_direction, &rec.normal);
+        let direction = if random_double() < fresnel_dielectric(wo.z(), eta) {
+            reflected
         } else {
-            // Must refract
-            direction = Vec3::refract(&unit_direction, &rec.normal, ri);
+            refract(&-unit_direction, &rec.normal, eta).unwrap_or(reflected)
+        };
+        let scattered = Ray::new_tm(rec.p, direction, r_in.time());
+        Some(ScatterRecord::specular(attenuation, scattered))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let distribution = self.distribution(rec);
+        if distribution.is_smooth() {
+            return Color::default();
+        }
+        let frame = shading_frame(rec);
+        let wo = frame.to_local(&-unit_vector(&r_in.direction()));
+        let wi = frame.to_local(&unit_vector(&scattered.direction()));
+        self.transmittance(r_in, rec) * dielectric_eval(&distribution, self.eta(rec), &wo, &wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let distribution = self.distribution(rec);
+        if distribution.is_smooth() {
+            return 0.;
+        }
+        let frame = shading_frame(rec);
+        let wo = frame.to_local(&-unit_vector(&r_in.direction()));
+        let wi = frame.to_local(&unit_vector(&scattered.direction()));
+        dielectric_pdf(&distribution, self.eta(rec), &wo, &wi)
+    }
+}
+
+#[derive(Debug)]
+pub struct DiffuseLight {
+    tex: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self {
+            tex: Arc::new(SolidColor::new(emit)),
+        }
+    }
+
+    pub fn with_texture(tex: Arc<dyn Texture>) -> Self {
+        Self { tex }
+    }
+}
+
+impl Material for DiffuseLight {
+    // Lights only emit, they never reflect incoming rays
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.tex.value(u, v, p)
+    }
+}
+
+#[derive(Debug)]
+pub struct Isotropic {
+    tex: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            tex: Arc::new(SolidColor::new(albedo)),
         }
-        *scattered = Ray::new_tm(rec.p, direction, r_in.time());
-        true
+    }
+
+    pub fn with_texture(tex: Arc<dyn Texture>) -> Self {
+        Self { tex }
+    }
+}
+
+impl Material for Isotropic {
+    // Phase function of participating media, scatter equally in every direction
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let attenuation = self.tex.sample(rec);
+        Some(ScatterRecord::with_pdf(
+            attenuation,
+            Arc::new(SpherePdf::new()),
+        ))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.tex.sample(rec) * self.pdf(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1. / (4. * PI)
     }
 }
@@ -0,0 +1,219 @@
+use std::ops::Mul;
+
+use crate::{
+    ray::Point3,
+    utils::degrees_to_radians,
+    vec3::{unit_vector, Vec3},
+};
+
+// Row-major 4x4 matrix for affine transforms of points and vectors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        Self::new([
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Self::new([
+            [1., 0., 0., offset.x()],
+            [0., 1., 0., offset.y()],
+            [0., 0., 1., offset.z()],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn scaling(s: Vec3) -> Self {
+        Self::new([
+            [s.x(), 0., 0., 0.],
+            [0., s.y(), 0., 0.],
+            [0., 0., s.z(), 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    // Counter-clockwise rotation in degrees around an arbitrary axis (Rodrigues' formula)
+    pub fn rotation(axis: Vec3, degrees: f64) -> Self {
+        let a = unit_vector(&axis);
+        let theta = degrees_to_radians(degrees);
+        let (sin, cos) = (f64::sin(theta), f64::cos(theta));
+        let t = 1. - cos;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        Self::new([
+            [
+                t * x * x + cos,
+                t * x * y - sin * z,
+                t * x * z + sin * y,
+                0.,
+            ],
+            [
+                t * x * y + sin * z,
+                t * y * y + cos,
+                t * y * z - sin * x,
+                0.,
+            ],
+            [
+                t * x * z - sin * y,
+                t * y * z + sin * x,
+                t * z * z + cos,
+                0.,
+            ],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn rotation_x(degrees: f64) -> Self {
+        Self::rotation(Vec3::new(1., 0., 0.), degrees)
+    }
+    pub fn rotation_y(degrees: f64) -> Self {
+        Self::rotation(Vec3::new(0., 1., 0.), degrees)
+    }
+    pub fn rotation_z(degrees: f64) -> Self {
+        Self::rotation(Vec3::new(0., 0., 1.), degrees)
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self::new(m)
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1. / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self::new(inv))
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w == 1. {
+            Point3::new(x, y, z)
+        } else {
+            Point3::new(x, y, z) / w
+        }
+    }
+
+    // Vectors are directions, so they ignore the translation part of the matrix
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    // The right-hand side is applied first: (a * b).transform_point(p) == a(b(p))
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self::new(m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: &Mat4, b: &Mat4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!(f64::abs(a.m[i][j] - b.m[i][j]) < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_of_composite() {
+        let m = Mat4::translation(Vec3::new(1., -2., 3.))
+            * Mat4::rotation(Vec3::new(1., 1., 0.), 33.)
+            * Mat4::scaling(Vec3::new(2., 0.5, 3.));
+        let inv = m.inverse().unwrap();
+        assert_near(&(m * inv), &Mat4::identity());
+        assert_near(&(inv * m), &Mat4::identity());
+    }
+
+    #[test]
+    fn singular_has_no_inverse() {
+        assert!(Mat4::scaling(Vec3::new(1., 0., 1.)).inverse().is_none());
+    }
+
+    #[test]
+    fn rotation_y_quarter_turn() {
+        let p = Mat4::rotation_y(90.).transform_point(&Point3::new(1., 0., 0.));
+        assert!((p - Point3::new(0., 0., -1.)).near_zero());
+    }
+
+    #[test]
+    fn vectors_ignore_translation() {
+        let m = Mat4::translation(Vec3::new(5., 5., 5.));
+        assert_eq!(
+            m.transform_vector(&Vec3::new(0., 1., 0.)),
+            Vec3::new(0., 1., 0.)
+        );
+        assert_eq!(
+            m.transform_point(&Point3::new(0., 1., 0.)),
+            Point3::new(5., 6., 5.)
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    linear_bvh::BVHIndex,
+    material::Material,
+    ray::{Point3, Ray},
+    triangle::{intersect, set_hit_record},
+    vec3::Vec3,
+};
+
+/// An indexed triangle mesh with shared vertex buffers.
+/// The mesh keeps its own BVH over triangle indices, so the whole model can be added to a scene
+/// as a single hittable instead of one object per triangle.
+#[derive(Debug)]
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<[f64; 2]>>,
+    indices: Vec<[usize; 3]>,
+    material: Option<Arc<dyn Material>>,
+    bvh: BVHIndex,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self::with_attributes(positions, None, None, indices, material)
+    }
+
+    /// Normals and UVs, when given, are indexed by the same indices as the positions.
+    pub fn with_attributes(
+        positions: Vec<Point3>,
+        normals: Option<Vec<Vec3>>,
+        uvs: Option<Vec<[f64; 2]>>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let vertex_count = positions.len();
+        assert!(
+            indices.iter().flatten().all(|&i| i < vertex_count),
+            "triangle index out of range of the vertex buffer"
+        );
+        assert!(normals.as_ref().is_none_or(|n| n.len() == vertex_count));
+        assert!(uvs.as_ref().is_none_or(|uv| uv.len() == vertex_count));
+
+        let mut mesh = Self {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material: Some(material),
+            bvh: BVHIndex::default(),
+        };
+        mesh.build_bvh();
+        mesh
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn triangle_bbox(&self, tri: &[usize; 3]) -> AABB {
+        let [a, b, c] = tri.map(|i| self.positions[i]);
+        AABB::with_boxes(&AABB::with_points(&a, &b), &AABB::with_points(&c, &c)).pad_to_minimums()
+    }
+
+    fn build_bvh(&mut self) {
+        let boxes: Vec<AABB> = self.indices.iter().map(|t| self.triangle_bbox(t)).collect();
+        let (bvh, order) = BVHIndex::build(&boxes);
+        self.bvh = bvh;
+
+        // Reorder the triangles so each leaf references a contiguous range
+        self.indices = order.iter().map(|&i| self.indices[i]).collect();
+    }
+
+    fn hit_triangle(&self, index: usize, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let [i0, i1, i2] = self.indices[index];
+        let (v0, v1, v2) = (
+            &self.positions[i0],
+            &self.positions[i1],
+            &self.positions[i2],
+        );
+        match intersect(v0, v1, v2, r, ray_t) {
+            Some(hit) => {
+                set_hit_record(
+                    [v0, v1, v2],
+                    self.normals.as_ref().map(|n| [&n[i0], &n[i1], &n[i2]]),
+                    self.uvs.as_ref().map(|uv| [&uv[i0], &uv[i1], &uv[i2]]),
+                    hit,
+                    r,
+                    rec,
+                );
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let hit_anything = self.bvh.hit(r, ray_t, rec, |i, r, interval, rec| {
+            self.hit_triangle(i, r, interval, rec)
+        });
+        if hit_anything {
+            rec.material = self.material.clone();
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bvh.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian, triangle::Triangle};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    // A wavy n x n grid of quads, split into two triangles each
+    fn grid(n: usize) -> (Vec<Point3>, Vec<[usize; 3]>) {
+        let mut positions = Vec::new();
+        for j in 0..=n {
+            for i in 0..=n {
+                let (x, z) = (i as f64, j as f64);
+                positions.push(Point3::new(x, f64::sin(x) * f64::cos(z), z));
+            }
+        }
+        let mut indices = Vec::new();
+        for j in 0..n {
+            for i in 0..n {
+                let a = j * (n + 1) + i;
+                let b = a + 1;
+                let c = a + n + 1;
+                let d = c + 1;
+                indices.push([a, c, b]);
+                indices.push([b, c, d]);
+            }
+        }
+        (positions, indices)
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let (positions, indices) = grid(16);
+        let triangles: Vec<Triangle> = indices
+            .iter()
+            .map(|&[a, b, c]| Triangle::new(positions[a], positions[b], positions[c], material()))
+            .collect();
+        let mesh = TriangleMesh::new(positions, indices, material());
+        assert_eq!(mesh.triangle_count(), 16 * 16 * 2);
+
+        for k in 0..200 {
+            let x = 0.3 + (k % 20) as f64 * 0.79;
+            let z = 0.1 + (k / 20) as f64 * 1.57;
+            let r = Ray::new(Point3::new(x, 5., z), Vec3::new(0.05, -1., 0.02));
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+
+            let mut expected = HitRecord::default();
+            let mut hit_any = false;
+            let mut closest = ray_t.max;
+            for tri in &triangles {
+                if tri.hit(&r, Interval::new(ray_t.min, closest), &mut expected) {
+                    hit_any = true;
+                    closest = expected.t;
+                }
+            }
+
+            let mut rec = HitRecord::default();
+            assert_eq!(mesh.hit(&r, ray_t, &mut rec), hit_any);
+            if hit_any {
+                assert!(f64::abs(rec.t - expected.t) < 1e-9);
+                assert!((rec.normal - expected.normal).near_zero());
+            }
+        }
+    }
+
+    #[test]
+    fn bbox_covers_all_vertices() {
+        let (positions, indices) = grid(4);
+        let mesh = TriangleMesh::new(positions.clone(), indices, material());
+        let bbox = mesh.bounding_box();
+        for p in positions {
+            for axis in 0..3 {
+                assert!(bbox.axis_interval(axis).contains(p[axis as usize]));
+            }
+        }
+    }
+}
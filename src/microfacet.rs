@@ -0,0 +1,479 @@
+use std::f64::consts::PI;
+
+use crate::{
+    color::Color,
+    hittable::HitRecord,
+    onb::Onb,
+    pdf::Pdf,
+    utils::random_double,
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+
+// Microfacet models describe a rough surface as many tiny mirrors (microfacets) whose normals
+// follow a distribution around the shading normal. Everything here works in the local shading
+// frame, where the surface normal is +Z.
+
+// Frame that microfacet materials work in, +Z along the shading normal and +X along the
+// tangent, so anisotropic roughness follows the surface parameterization
+pub fn shading_frame(rec: &HitRecord) -> Onb {
+    Onb::with_tangent(&rec.normal, &rec.tangent)
+}
+
+// The Trowbridge-Reitz (GGX) distribution of microfacet normals. alpha_x and alpha_y are the
+// roughness along the two tangent directions, equal for isotropic surfaces
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self {
+            alpha_x: f64::max(alpha_x, 1e-4),
+            alpha_y: f64::max(alpha_y, 1e-4),
+        }
+    }
+
+    // Perceptual roughness in [0, 1], squared so that the look changes evenly across the range
+    pub fn from_roughness(roughness_x: f64, roughness_y: f64) -> Self {
+        let roughness_x = roughness_x.clamp(0., 1.);
+        let roughness_y = roughness_y.clamp(0., 1.);
+        Self::new(roughness_x * roughness_x, roughness_y * roughness_y)
+    }
+
+    // So smooth that it is better treated as a perfect mirror, sampling the distribution would
+    // only produce fireflies
+    pub fn is_smooth(&self) -> bool {
+        f64::max(self.alpha_x, self.alpha_y) < 1e-3
+    }
+
+    // Density of microfacet normals, normalized so that their projected area is one
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2_theta = wm.z() * wm.z();
+        if cos2_theta <= 0. {
+            return 0.;
+        }
+        let e = ((wm.x() / self.alpha_x).powi(2) + (wm.y() / self.alpha_y).powi(2)) / cos2_theta;
+        1. / (PI * self.alpha_x * self.alpha_y * cos2_theta * cos2_theta * (1. + e) * (1. + e))
+    }
+
+    // Smith's auxiliary function, the area of microfacets hidden from direction w per unit of
+    // visible area
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2_theta = w.z() * w.z();
+        if cos2_theta <= 0. {
+            return f64::INFINITY;
+        }
+        let alpha2_tan2_theta =
+            ((w.x() * self.alpha_x).powi(2) + (w.y() * self.alpha_y).powi(2)) / cos2_theta;
+        (f64::sqrt(1. + alpha2_tan2_theta) - 1.) / 2.
+    }
+
+    // Fraction of microfacets visible from w
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+
+    // Fraction of microfacets visible from both wo and wi
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the microfacet normals seen from w, weighted by how much of each is visible
+    pub fn visible_d(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        if w.z() == 0. {
+            return 0.;
+        }
+        self.g1(w) / f64::abs(w.z()) * self.d(wm) * f64::abs(dot(*w, *wm))
+    }
+
+    // Sample a microfacet normal from visible_d(w, wm). Heitz 2018, "Sampling the GGX
+    // Distribution of Visible Normals": stretch the view direction to the configuration where
+    // the surface is a hemisphere, pick a point on its visible half, and stretch back
+    pub fn sample_visible_normal(&self, w: &Vec3) -> Vec3 {
+        let w = if w.z() < 0. { -*w } else { *w };
+        let wh = unit_vector(&Vec3::new(
+            self.alpha_x * w.x(),
+            self.alpha_y * w.y(),
+            w.z(),
+        ));
+
+        let length_squared = wh.x() * wh.x() + wh.y() * wh.y();
+        let t1 = if length_squared > 0. {
+            Vec3::new(-wh.y(), wh.x(), 0.) / f64::sqrt(length_squared)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let t2 = cross(wh, t1);
+
+        // Uniform point on a disk, squeezed onto the part of the hemisphere visible from wh
+        let r = f64::sqrt(random_double());
+        let phi = 2. * PI * random_double();
+        let p1 = r * f64::cos(phi);
+        let s = 0.5 * (1. + wh.z());
+        let p2 = (1. - s) * f64::sqrt(1. - p1 * p1) + s * r * f64::sin(phi);
+        let nh = t1 * p1 + t2 * p2 + wh * f64::sqrt(f64::max(0., 1. - p1 * p1 - p2 * p2));
+
+        unit_vector(&Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            f64::max(1e-6, nh.z()),
+        ))
+    }
+}
+
+// Fresnel reflectance of a conductor with complex index of refraction eta + i k, per channel
+pub fn fresnel_conductor(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    let cos_theta_i = cos_theta_i.clamp(0., 1.);
+    let cos2 = cos_theta_i * cos_theta_i;
+    let sin2 = 1. - cos2;
+    let channel = |eta: f64, k: f64| {
+        let (eta2, k2) = (eta * eta, k * k);
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = f64::sqrt(f64::max(0., t0 * t0 + 4. * eta2 * k2));
+        let t1 = a2_plus_b2 + cos2;
+        let a = f64::sqrt(f64::max(0., 0.5 * (a2_plus_b2 + t0)));
+        let t2 = 2. * cos_theta_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Color::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+// Exact Fresnel reflectance of a dielectric interface, eta being the index of refraction below
+// the surface (-Z) over the one above. A negative cos_theta_i means the light arrives from below
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_theta_i, eta) = if cos_theta_i < 0. {
+        (-cos_theta_i, 1. / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let cos_theta_i = f64::min(cos_theta_i, 1.);
+    let sin2_theta_t = (1. - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1. {
+        // Total internal reflection
+        return 1.;
+    }
+    let cos_theta_t = f64::sqrt(1. - sin2_theta_t);
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+// Direction w refracts into through a surface with normal n on its side, eta being the index of
+// refraction on the far side over the one on w's side. None on total internal reflection
+pub fn refract(w: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = dot(*n, *w);
+    let sin2_theta_t = f64::max(0., 1. - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1. {
+        return None;
+    }
+    let cos_theta_t = f64::sqrt(1. - sin2_theta_t);
+    Some(-*w / eta + *n * (cos_theta_i / eta - cos_theta_t))
+}
+
+// Generalized half vector of a reflected or refracted pair of directions, the microfacet normal
+// that scatters one into the other, facing +Z. eta is the index of refraction below the surface
+// over the one above. None for pairs no microfacet can connect
+fn half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<(Vec3, f64)> {
+    if wo.z() == 0. || wi.z() == 0. {
+        return None;
+    }
+    let etap = if wo.z() * wi.z() > 0. {
+        1.
+    } else if wo.z() > 0. {
+        eta
+    } else {
+        1. / eta
+    };
+    let wm = *wi * etap + *wo;
+    if wm.length_squared() == 0. {
+        return None;
+    }
+    let wm = unit_vector(&wm);
+    let wm = if wm.z() < 0. { -wm } else { wm };
+    // Microfacets facing away from either direction can't be the one that connects them
+    if dot(wm, *wi) * wi.z() < 0. || dot(wm, *wo) * wo.z() < 0. {
+        return None;
+    }
+    Some((wm, etap))
+}
+
+// Light arriving from wo that a rough dielectric scatters towards wi, times |cos(theta_i)|. Both
+// are in the local frame and may be on either side of the surface.
+// Radiance isn't scaled by the squared ratio of the indices of refraction when crossing the
+// interface, matching Dielectric; it cancels out for closed objects anyway
+pub fn dielectric_eval(distribution: &TrowbridgeReitz, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+    let Some((wm, etap)) = half_vector(wo, wi, eta) else {
+        return 0.;
+    };
+    let fresnel = fresnel_dielectric(dot(*wo, wm), eta);
+    if etap == 1. {
+        return distribution.d(&wm) * distribution.g(wo, wi) * fresnel / f64::abs(4. * wo.z());
+    }
+    let denom = (dot(*wi, wm) + dot(*wo, wm) / etap).powi(2) * wo.z();
+    distribution.d(&wm)
+        * (1. - fresnel)
+        * distribution.g(wo, wi)
+        * f64::abs(dot(*wi, wm) * dot(*wo, wm) / denom)
+}
+
+// Density of the directions DielectricPdf samples, in the local frame
+pub fn dielectric_pdf(distribution: &TrowbridgeReitz, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+    let Some((wm, etap)) = half_vector(wo, wi, eta) else {
+        return 0.;
+    };
+    let reflectance = fresnel_dielectric(dot(*wo, wm), eta);
+    if etap == 1. {
+        return distribution.visible_d(wo, &wm) / (4. * f64::abs(dot(*wo, wm))) * reflectance;
+    }
+    let denom = (dot(*wi, wm) + dot(*wo, wm) / etap).powi(2);
+    let dwm_dwi = f64::abs(dot(*wi, wm)) / denom;
+    distribution.visible_d(wo, &wm) * dwm_dwi * (1. - reflectance)
+}
+
+// Directions of light scattered by the visible microfacets of a rough dielectric, reflected or
+// refracted in proportion to the Fresnel reflectance. Both are in the local shading frame, and
+// eta is the index of refraction below the surface over the one above
+#[derive(Debug)]
+pub struct DielectricPdf {
+    frame: Onb,
+    wo: Vec3,
+    eta: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl DielectricPdf {
+    pub fn new(frame: Onb, wo: Vec3, eta: f64, distribution: TrowbridgeReitz) -> Self {
+        Self {
+            frame,
+            wo,
+            eta,
+            distribution,
+        }
+    }
+}
+
+impl Pdf for DielectricPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = unit_vector(&self.frame.to_local(direction));
+        dielectric_pdf(&self.distribution, self.eta, &self.wo, &wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let wm = self.distribution.sample_visible_normal(&self.wo);
+        let reflectance = fresnel_dielectric(dot(self.wo, wm), self.eta);
+        let reflected = Vec3::reflect(&-self.wo, &wm);
+        let wi = if random_double() < reflectance {
+            reflected
+        } else {
+            // Flip the microfacet to the side of wo when refracting from below
+            let (n, eta) = if dot(self.wo, wm) < 0. {
+                (-wm, 1. / self.eta)
+            } else {
+                (wm, self.eta)
+            };
+            refract(&self.wo, &n, eta).unwrap_or(reflected)
+        };
+        self.frame.transform(&wi)
+    }
+}
+
+// Directions of light reflected off the visible microfacets, for a ray arriving from wo. Both
+// are in the local shading frame
+#[derive(Debug)]
+pub struct MicrofacetReflectionPdf {
+    frame: Onb,
+    wo: Vec3,
+    distribution: TrowbridgeReitz,
+}
+
+impl MicrofacetReflectionPdf {
+    pub fn new(frame: Onb, wo: Vec3, distribution: TrowbridgeReitz) -> Self {
+        Self {
+            frame,
+            wo,
+            distribution,
+        }
+    }
+}
+
+// Density of reflecting wo into wi off microfacets sampled with visible_d, in the local frame
+pub fn reflection_pdf(distribution: &TrowbridgeReitz, wo: &Vec3, wi: &Vec3) -> f64 {
+    if wo.z() * wi.z() <= 0. {
+        return 0.;
+    }
+    let wm = unit_vector(&(*wo + *wi));
+    // The half vector is mapped to the reflected direction with Jacobian 1 / (4 |wo . wm|)
+    distribution.visible_d(wo, &wm) / (4. * f64::abs(dot(*wo, wm)))
+}
+
+// Light arriving from wo that the microfacets reflect towards wi, times cos(theta_i), leaving
+// out the Fresnel factor, which depends on the material: D G / (4 cos(theta_o) cos(theta_i)),
+// with the cosines cancelling. Both are in the local frame, above the surface
+pub fn reflection_eval(distribution: &TrowbridgeReitz, wo: &Vec3, wi: &Vec3) -> f64 {
+    let wm = unit_vector(&(*wo + *wi));
+    distribution.d(&wm) * distribution.g(wo, wi) / (4. * wo.z())
+}
+
+impl Pdf for MicrofacetReflectionPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = unit_vector(&self.frame.to_local(direction));
+        reflection_pdf(&self.distribution, &self.wo, &wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let wm = self.distribution.sample_visible_normal(&self.wo);
+        let wi = Vec3::reflect(&-self.wo, &wm);
+        self.frame.transform(&wi)
+    }
+}
+
+// Integral of f over all directions by the midpoint rule on a steps by steps grid over
+// cos(theta) and phi, which are both uniform in solid angle. Used to check densities in tests
+#[cfg(test)]
+pub fn integrate_sphere(steps: usize, f: impl Fn(&Vec3) -> f64) -> f64 {
+    let mut integral = 0.;
+    for i in 0..steps {
+        let cos_theta = -1. + 2. * (i as f64 + 0.5) / steps as f64;
+        let sin_theta = f64::sqrt(1. - cos_theta * cos_theta);
+        for j in 0..steps {
+            let phi = 2. * PI * (j as f64 + 0.5) / steps as f64;
+            integral += f(&Vec3::new(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                cos_theta,
+            ));
+        }
+    }
+    integral * 4. * PI / (steps * steps) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::SpherePdf;
+
+    #[test]
+    fn projected_normals_integrate_to_one() {
+        let distribution = TrowbridgeReitz::new(0.3, 0.6);
+        let sphere = SpherePdf::new();
+        let samples = 200_000;
+        let sum: f64 = (0..samples)
+            .map(|_| {
+                let wm = sphere.generate();
+                if wm.z() <= 0. {
+                    return 0.;
+                }
+                distribution.d(&wm) * wm.z() / sphere.value(&wm)
+            })
+            .sum();
+        let integral = sum / samples as f64;
+        assert!(f64::abs(integral - 1.) < 0.03, "integral = {}", integral);
+    }
+
+    #[test]
+    fn sampled_reflections_match_density() {
+        // The density integrated over the upper hemisphere must equal the share of sampled
+        // reflections that end up there, the rest bounce below the surface
+        let distribution = TrowbridgeReitz::new(0.5, 0.2);
+        let wo = unit_vector(&Vec3::new(0.6, 0.2, 0.5));
+        let pdf = MicrofacetReflectionPdf::new(Onb::new(&Vec3::new(0., 0., 1.)), wo, distribution);
+        let sphere = SpherePdf::new();
+
+        let samples = 200_000;
+        let above = (0..samples).filter(|_| pdf.generate().z() > 0.).count();
+        let sum: f64 = (0..samples)
+            .map(|_| {
+                let wi = sphere.generate();
+                pdf.value(&wi) / sphere.value(&wi)
+            })
+            .sum();
+        let fraction = above as f64 / samples as f64;
+        let integral = sum / samples as f64;
+        assert!(fraction > 0.8);
+        assert!(
+            f64::abs(integral - fraction) < 0.03,
+            "{} vs {}",
+            integral,
+            fraction
+        );
+    }
+
+    #[test]
+    fn sampled_dielectric_directions_match_density() {
+        // Reflected and refracted directions together cover the whole sphere, from either side.
+        // Only samples pushed to the wrong side of the surface by the microfacet are lost
+        let distribution = TrowbridgeReitz::new(0.3, 0.3);
+        for wo in [Vec3::new(0.5, 0.1, 0.6), Vec3::new(-0.2, 0.3, -0.8)] {
+            let wo = unit_vector(&wo);
+            let pdf = DielectricPdf::new(Onb::new(&Vec3::new(0., 0., 1.)), wo, 1.5, distribution);
+
+            // Follow the steps of generate() to tell which samples are lost
+            let samples = 200_000;
+            let lost = (0..samples)
+                .filter(|_| {
+                    let wm = distribution.sample_visible_normal(&wo);
+                    if random_double() < fresnel_dielectric(dot(wo, wm), 1.5) {
+                        return Vec3::reflect(&-wo, &wm).z() * wo.z() <= 0.;
+                    }
+                    let (n, eta) = if dot(wo, wm) < 0. {
+                        (-wm, 1. / 1.5)
+                    } else {
+                        (wm, 1.5)
+                    };
+                    refract(&wo, &n, eta).is_some_and(|wi| wi.z() * wo.z() >= 0.)
+                })
+                .count();
+
+            let integral = integrate_sphere(1000, |wi| pdf.value(wi));
+
+            let expected = 1. - lost as f64 / samples as f64;
+            assert!(expected > 0.85);
+            assert!(
+                f64::abs(integral - expected) < 0.005,
+                "{} vs {}",
+                integral,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn dielectric_eval_is_reciprocal_for_reflection() {
+        let distribution = TrowbridgeReitz::new(0.4, 0.4);
+        let wo = unit_vector(&Vec3::new(0.3, 0.2, 0.7));
+        let wi = unit_vector(&Vec3::new(-0.5, 0.1, 0.4));
+        let forward = dielectric_eval(&distribution, 1.5, &wo, &wi) / wi.z();
+        let backward = dielectric_eval(&distribution, 1.5, &wi, &wo) / wo.z();
+        assert!(f64::abs(forward - backward) < 1e-9);
+    }
+
+    #[test]
+    fn dielectric_fresnel_limits() {
+        // ((n - 1) / (n + 1))^2 at normal incidence, from either side
+        assert!(f64::abs(fresnel_dielectric(1., 1.5) - 0.04) < 1e-9);
+        assert!(f64::abs(fresnel_dielectric(-1., 1. / 1.5) - 0.04) < 1e-9);
+        // Past the critical angle inside glass everything is reflected
+        assert_eq!(fresnel_dielectric(0.5, 1. / 1.5), 1.);
+    }
+
+    #[test]
+    fn conductor_fresnel_limits() {
+        let (eta, k) = (Color::new(0.2, 0.9, 1.1), Color::new(3.9, 2.5, 2.1));
+        let normal = fresnel_conductor(1., &eta, &k);
+        // ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2) at normal incidence
+        let expected = ((0.2f64 - 1.).powi(2) + 3.9f64.powi(2)) / (1.2f64.powi(2) + 3.9f64.powi(2));
+        assert!(f64::abs(normal.x() - expected) < 1e-9);
+        let grazing = fresnel_conductor(0., &eta, &k);
+        assert!((grazing - Color::new(1., 1., 1.)).near_zero());
+    }
+}
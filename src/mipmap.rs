@@ -0,0 +1,282 @@
+// Texel storage for image textures: the image and a pyramid of ever smaller copies of it, each
+// half the size of the one before. Distant surfaces read from the smaller copies, whose texels
+// already average over everything a single ray stands for, instead of picking one texel of the
+// full image at random and sparkling
+
+// What happens to texture coordinates outside of [0, 1]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    // The edge texels stretch out forever
+    #[default]
+    Clamp,
+    // The image tiles
+    Repeat,
+    // The image tiles, flipped every other time so the seams line up
+    Mirror,
+}
+
+impl WrapMode {
+    // Texel index for any integer coordinate along an axis of the given size
+    fn wrap(&self, x: i64, size: usize) -> usize {
+        let size = size as i64;
+        let x = match self {
+            WrapMode::Clamp => x.clamp(0, size - 1),
+            WrapMode::Repeat => x.rem_euclid(size),
+            WrapMode::Mirror => {
+                let x = x.rem_euclid(2 * size);
+                if x < size {
+                    x
+                } else {
+                    2 * size - 1 - x
+                }
+            }
+        };
+        x as usize
+    }
+}
+
+// How texels are combined into a value
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // The texel under the point, blocky when magnified
+    Nearest,
+    // Blend of the four nearest texels
+    Bilinear,
+    // Bilinear in the two pyramid levels closest to the footprint, blended
+    #[default]
+    Trilinear,
+}
+
+#[derive(Debug)]
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 4]>,
+}
+
+impl Level {
+    // Half the size in each direction, rounded down. Each new texel averages the patch of the
+    // old level it covers, weighted by how much of each old texel lies in the patch, so along
+    // odd sides the texels in between are shared and none are dropped
+    fn downsample(&self) -> Level {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let rows = Self::coverage(y, height, self.height);
+            for x in 0..width {
+                let columns = Self::coverage(x, width, self.width);
+                let mut sum = [0.; 4];
+                for &(sy, wy) in &rows {
+                    for &(sx, wx) in &columns {
+                        let texel = self.texels[sy * self.width + sx];
+                        for c in 0..4 {
+                            sum[c] += texel[c] * wx * wy;
+                        }
+                    }
+                }
+                texels.push(sum);
+            }
+        }
+        Level {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    // Texels of an axis source_size long that texel i of the same axis shrunk to size covers,
+    // with weights summing to 1
+    fn coverage(i: usize, size: usize, source_size: usize) -> Vec<(usize, f32)> {
+        let scale = source_size as f32 / size as f32;
+        let (start, end) = (i as f32 * scale, (i + 1) as f32 * scale);
+        (start.floor() as usize..(end.ceil() as usize).min(source_size))
+            .map(|j| {
+                let overlap = end.min(j as f32 + 1.) - start.max(j as f32);
+                (j, overlap / scale)
+            })
+            .collect()
+    }
+
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> [f32; 4] {
+        self.texels[wrap.wrap(y, self.height) * self.width + wrap.wrap(x, self.width)]
+    }
+
+    fn nearest(&self, u: f64, v: f64, wrap: WrapMode) -> [f32; 4] {
+        let x = f64::floor(u * self.width as f64) as i64;
+        let y = f64::floor(v * self.height as f64) as i64;
+        self.texel(x, y, wrap)
+    }
+
+    fn bilinear(&self, u: f64, v: f64, wrap: WrapMode) -> [f32; 4] {
+        // Texel centers sit at half integer coordinates
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = lerp(self.texel(x0, y0, wrap), self.texel(x0 + 1, y0, wrap), fx);
+        let bottom = lerp(
+            self.texel(x0, y0 + 1, wrap),
+            self.texel(x0 + 1, y0 + 1, wrap),
+            fx,
+        );
+        lerp(top, bottom, fy)
+    }
+}
+
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|c| a[c] + (b[c] - a[c]) * t)
+}
+
+#[derive(Debug)]
+pub struct MipMap {
+    levels: Vec<Level>,
+}
+
+impl MipMap {
+    // texels are RGBA, row by row from the top left corner
+    pub fn new(width: usize, height: usize, texels: Vec<[f32; 4]>) -> Self {
+        assert_eq!(texels.len(), width * height);
+        let mut levels = vec![Level {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(last) = levels.last() {
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    // Value at (u, v), with v running up from the bottom of the image, averaged over a square
+    // footprint that is footprint wide in uv units. Only trilinear filtering uses the pyramid,
+    // the others always read the full image
+    pub fn lookup(
+        &self,
+        u: f64,
+        v: f64,
+        footprint: f64,
+        wrap: WrapMode,
+        filter: Filter,
+    ) -> [f32; 4] {
+        let v = 1. - v; // Images are stored top row first
+        match filter {
+            Filter::Nearest => self.levels[0].nearest(u, v, wrap),
+            Filter::Bilinear => self.levels[0].bilinear(u, v, wrap),
+            Filter::Trilinear => {
+                // Level whose texels are about as wide as the footprint
+                let texels = footprint * self.width().max(self.height()) as f64;
+                let level = f64::log2(texels.max(1.)).min((self.levels() - 1) as f64);
+                let lower = level.floor() as usize;
+                let upper = (lower + 1).min(self.levels() - 1);
+                let t = (level - lower as f64) as f32;
+                lerp(
+                    self.levels[lower].bilinear(u, v, wrap),
+                    self.levels[upper].bilinear(u, v, wrap),
+                    t,
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checkerboard of single black and white texels, opaque
+    fn checker(size: usize) -> MipMap {
+        let texels = (0..size * size)
+            .map(|i| {
+                let c = ((i % size + i / size) % 2) as f32;
+                [c, c, c, 1.]
+            })
+            .collect();
+        MipMap::new(size, size, texels)
+    }
+
+    #[test]
+    fn wrap_modes() {
+        assert_eq!(WrapMode::Clamp.wrap(-3, 4), 0);
+        assert_eq!(WrapMode::Clamp.wrap(7, 4), 3);
+        assert_eq!(WrapMode::Repeat.wrap(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.wrap(5, 4), 1);
+        assert_eq!(WrapMode::Mirror.wrap(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.wrap(4, 4), 3);
+        assert_eq!(WrapMode::Mirror.wrap(9, 4), 1);
+    }
+
+    #[test]
+    fn bilinear_blends_between_texel_centers() {
+        let map = MipMap::new(2, 1, vec![[0., 0., 0., 1.], [1., 1., 1., 1.]]);
+        let at = |u: f64| map.lookup(u, 0.5, 0., WrapMode::Clamp, Filter::Bilinear)[0];
+        assert_eq!(at(0.25), 0.);
+        assert_eq!(at(0.5), 0.5);
+        assert_eq!(at(0.75), 1.);
+        assert_eq!(
+            map.lookup(0.4, 0.5, 0., WrapMode::Clamp, Filter::Nearest)[0],
+            0.
+        );
+    }
+
+    #[test]
+    fn pyramid_averages_down_to_one_texel() {
+        let map = checker(8);
+        assert_eq!(map.levels(), 4);
+        // A footprint as wide as the texture reads the single average texel
+        let value = map.lookup(0.3, 0.6, 1., WrapMode::Repeat, Filter::Trilinear);
+        assert!(f32::abs(value[0] - 0.5) < 1e-6);
+        // A point footprint reads the full image
+        let value = map.lookup(
+            0.5 / 8.,
+            1. - 0.5 / 8.,
+            0.,
+            WrapMode::Repeat,
+            Filter::Trilinear,
+        );
+        assert_eq!(value[0], 0.);
+    }
+
+    #[test]
+    fn odd_sizes_shrink_to_one_texel() {
+        let map = MipMap::new(5, 3, vec![[1., 1., 1., 1.]; 15]);
+        assert_eq!(map.levels(), 3);
+        let value = map.lookup(0.5, 0.5, 1., WrapMode::Clamp, Filter::Trilinear);
+        assert_eq!(value, [1., 1., 1., 1.]);
+    }
+
+    #[test]
+    fn odd_sizes_average_every_texel() {
+        let map = MipMap::new(
+            3,
+            1,
+            vec![[0., 0., 0., 1.], [0., 0., 0., 1.], [1., 1., 1., 1.]],
+        );
+        assert_eq!(map.levels(), 2);
+        let value = map.lookup(0.5, 0.5, 1., WrapMode::Clamp, Filter::Trilinear);
+        assert!(f32::abs(value[0] - 1. / 3.) < 1e-6);
+
+        // Columns of 0 to 4, the last level is their mean
+        let texels = (0..15).map(|i| [(i % 5) as f32, 0., 0., 1.]).collect();
+        let map = MipMap::new(5, 3, texels);
+        let value = map.lookup(0.5, 0.5, 1., WrapMode::Clamp, Filter::Trilinear);
+        assert!(f32::abs(value[0] - 2.) < 1e-6);
+    }
+}
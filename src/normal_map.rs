@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use crate::{
+    color::Color,
+    hittable::HitRecord,
+    material::{Material, ScatterRecord},
+    onb::Onb,
+    ray::{Point3, Ray},
+    texture::Texture,
+    vec3::{dot, unit_vector},
+};
+
+// Step used to take the slope of a height texture, in uv and in world units
+const BUMP_DELTA: f64 = 1e-3;
+
+/// Where the detail that tilts the shading normal comes from
+#[derive(Debug)]
+pub enum Perturbation {
+    // Tangent space normal map, with red, green and blue in [0, 1] mapped to [-1, 1] along the
+    // tangent, bitangent and normal. The flat (0.5, 0.5, 1) leaves the normal as is
+    NormalMap(Arc<dyn Texture>),
+    // Height field from the first channel, tilting the normal down its slope. The slope is
+    // taken along u and v for image textures and along the surface for solid ones such as
+    // noise, and multiplied by strength
+    Bump {
+        height: Arc<dyn Texture>,
+        strength: f64,
+    },
+}
+
+/// Wraps a material so it scatters around a normal perturbed by a normal map or a bump map,
+/// giving flat geometry the look of fine surface detail. Only the shading normal and tangent
+/// frame change, front_face still comes from the geometry.
+#[derive(Debug)]
+pub struct NormalMapped {
+    material: Arc<dyn Material>,
+    perturbation: Perturbation,
+}
+
+impl NormalMapped {
+    pub fn new(material: Arc<dyn Material>, normal_map: Arc<dyn Texture>) -> Self {
+        Self {
+            material,
+            perturbation: Perturbation::NormalMap(normal_map),
+        }
+    }
+
+    pub fn bump(material: Arc<dyn Material>, height: Arc<dyn Texture>, strength: f64) -> Self {
+        Self {
+            material,
+            perturbation: Perturbation::Bump { height, strength },
+        }
+    }
+
+    // Copy of the hit record with the perturbed normal and a tangent frame around it
+    pub fn shade(&self, rec: &HitRecord) -> HitRecord {
+        let outward_normal = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let (tangent, bitangent) = if rec.tangent.near_zero() {
+            let frame = Onb::new(&outward_normal);
+            (frame.u(), frame.v())
+        } else {
+            (rec.tangent, rec.bitangent)
+        };
+
+        let perturbed = match &self.perturbation {
+            Perturbation::NormalMap(normal_map) => {
+                let c = normal_map.sample(rec) * 2. - Color::new(1., 1., 1.);
+                tangent * c.x() + bitangent * c.y() + outward_normal * c.z()
+            }
+            Perturbation::Bump { height, strength } => {
+                let height_at = |du: f64, dv: f64| {
+                    let p = rec.p + tangent * du + bitangent * dv;
+                    height.value(rec.u + du, rec.v + dv, &p).x()
+                };
+                let h = height_at(0., 0.);
+                let dhdu = (height_at(BUMP_DELTA, 0.) - h) / BUMP_DELTA;
+                let dhdv = (height_at(0., BUMP_DELTA) - h) / BUMP_DELTA;
+                outward_normal - (tangent * dhdu + bitangent * dhdv) * *strength
+            }
+        };
+        // Normals pointing into the surface would have it scatter light from the wrong side
+        if perturbed.near_zero() || dot(perturbed, outward_normal) <= 0. {
+            return rec.clone();
+        }
+
+        let perturbed = unit_vector(&perturbed);
+        let mut shaded = rec.clone();
+        shaded.normal = if rec.front_face {
+            perturbed
+        } else {
+            -perturbed
+        };
+        shaded.set_tangent_frame(&tangent, &bitangent);
+        shaded.uv_per_unit = rec.uv_per_unit;
+        shaded
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.material.scatter(r_in, &self.shade(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.material.eval(r_in, &self.shade(rec), scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.pdf(r_in, &self.shade(rec), scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.material.emitted(u, v, p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::Hittable, interval::Interval, material::Lambertian, quad::Quad,
+        texture::SolidColor, vec3::Vec3,
+    };
+
+    // Hit on the unit quad in the xy plane, with u along +x and v along +y
+    fn hit_quad(from_front: bool) -> HitRecord {
+        let quad = Quad::new(
+            Point3::new(0., 0., 0.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let z = if from_front { 1. } else { -1. };
+        let r = Ray::new(Point3::new(0.5, 0.5, z), Vec3::new(0., 0., -z));
+        let mut rec = HitRecord::default();
+        assert!(quad.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        rec
+    }
+
+    fn lambertian() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_normal() {
+        let rec = hit_quad(true);
+        let flat = Arc::new(SolidColor::with_rgb(0.5, 0.5, 1.));
+        let shaded = NormalMapped::new(lambertian(), flat).shade(&rec);
+        assert!((shaded.normal - rec.normal).near_zero());
+    }
+
+    #[test]
+    fn normal_map_tilts_along_tangent_from_both_sides() {
+        let tilted = Arc::new(SolidColor::with_rgb(1., 0.5, 1.));
+        let material = NormalMapped::new(lambertian(), tilted);
+        for from_front in [true, false] {
+            let rec = hit_quad(from_front);
+            let shaded = material.shade(&rec);
+            // The outward normal leans towards +u whichever side it is seen from
+            let outward = if from_front {
+                shaded.normal
+            } else {
+                -shaded.normal
+            };
+            assert!(outward.x() > 0.5 && outward.z() > 0.5);
+            assert_eq!(shaded.front_face, rec.front_face);
+            assert!(f64::abs(dot(shaded.tangent, shaded.normal)) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn bump_tilts_down_the_slope() {
+        // Height rising along u
+        #[derive(Debug)]
+        struct Ramp;
+        impl Texture for Ramp {
+            fn value(&self, u: f64, _v: f64, _p: &Point3) -> Color {
+                Color::new(u, u, u)
+            }
+        }
+        let rec = hit_quad(true);
+        let shaded = NormalMapped::bump(lambertian(), Arc::new(Ramp), 1.).shade(&rec);
+        let expected = unit_vector(&Vec3::new(-1., 0., 1.));
+        assert!((shaded.normal - expected).near_zero());
+    }
+}
@@ -0,0 +1,614 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::SplitWhitespace,
+    sync::Arc,
+};
+
+use crate::{
+    color::Color,
+    hittable::HittableList,
+    material::{Dielectric, Lambertian, Material, Metal},
+    mesh::TriangleMesh,
+    ray::Point3,
+    texture::{ColorSpace, ImageTexture, SolidColor, TextureError},
+    texture_nodes::Combine,
+    vec3::Vec3,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    // The file could not be read
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // A malformed statement in an .obj file, or in an .mtl file parsed from a string
+    Parse {
+        line: usize,
+        message: String,
+    },
+    // A malformed statement in a material library referenced by the .obj file
+    Material {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    // A texture map named by a material could not be loaded
+    Texture(TextureError),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => {
+                write!(f, "could not read '{}': {}", path.display(), source)
+            }
+            ObjError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ObjError::Material {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Texture(source) => write!(f, "could not load texture map: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Texture(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> ObjError {
+    ObjError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+fn read_to_string(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Load a Wavefront .obj file, along with the material libraries it references.
+/// Every group/material combination becomes one TriangleMesh in the returned list.
+pub fn load_obj(path: impl AsRef<Path>) -> Result<HittableList, ObjError> {
+    let path = path.as_ref();
+    let source = read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_obj(&source, base_dir)
+}
+
+/// Load a Wavefront .mtl material library.
+pub fn load_mtl(path: impl AsRef<Path>) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let path = path.as_ref();
+    let source = read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_mtl(&source, base_dir).map_err(|e| match e {
+        ObjError::Parse { line, message } => ObjError::Material {
+            path: path.to_path_buf(),
+            line,
+            message,
+        },
+        e => e,
+    })
+}
+
+fn parse_f64(token: Option<&str>, line: usize, what: &str) -> Result<f64, ObjError> {
+    let token = token.ok_or_else(|| parse_error(line, format!("missing {}", what)))?;
+    token
+        .parse::<f64>()
+        .map_err(|_| parse_error(line, format!("invalid {} '{}'", what, token)))
+}
+
+fn parse_vec3(tokens: &mut SplitWhitespace, line: usize, what: &str) -> Result<Vec3, ObjError> {
+    Ok(Vec3::new(
+        parse_f64(tokens.next(), line, what)?,
+        parse_f64(tokens.next(), line, what)?,
+        parse_f64(tokens.next(), line, what)?,
+    ))
+}
+
+// The rest of the line after the keyword, used for names and file paths that may contain spaces
+fn remainder<'a>(statement: &'a str, keyword: &str, line: usize) -> Result<&'a str, ObjError> {
+    let rest = statement[keyword.len()..].trim();
+    if rest.is_empty() {
+        return Err(parse_error(
+            line,
+            format!("'{}' needs an argument", keyword),
+        ));
+    }
+    Ok(rest)
+}
+
+// Resolve a 1-based (or negative, relative to the end) OBJ index into a 0-based one
+fn resolve_index(token: &str, count: usize, line: usize, what: &str) -> Result<usize, ObjError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| parse_error(line, format!("invalid {} index '{}'", what, token)))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(parse_error(
+            line,
+            format!("{} index {} out of range ({} defined)", what, index, count),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+// Triangles sharing a group and a material, with vertices de-duplicated into a single index
+// buffer since OBJ indexes positions, texture coordinates and normals separately
+#[derive(Debug)]
+struct MeshBuilder {
+    material: Arc<dyn Material>,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f64; 2]>,
+    indices: Vec<[usize; 3]>,
+    lookup: HashMap<VertexKey, usize>,
+    has_normals: bool,
+    has_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn new(material: Arc<dyn Material>) -> Self {
+        Self {
+            material,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+            lookup: HashMap::new(),
+            has_normals: true,
+            has_uvs: true,
+        }
+    }
+
+    fn vertex(&mut self, key: VertexKey, obj: &ObjData) -> usize {
+        if let Some(&index) = self.lookup.get(&key) {
+            return index;
+        }
+        let (v, vt, vn) = key;
+        let index = self.positions.len();
+        self.positions.push(obj.positions[v]);
+        match vt {
+            Some(vt) => self.uvs.push(obj.uvs[vt]),
+            None => self.has_uvs = false,
+        }
+        match vn {
+            Some(vn) => self.normals.push(obj.normals[vn]),
+            None => self.has_normals = false,
+        }
+        self.lookup.insert(key, index);
+        index
+    }
+
+    fn build(self) -> Option<TriangleMesh> {
+        if self.indices.is_empty() {
+            return None;
+        }
+        // Attributes are only kept when every vertex of the mesh provides them
+        let normals = if self.has_normals {
+            Some(self.normals)
+        } else {
+            None
+        };
+        let uvs = if self.has_uvs { Some(self.uvs) } else { None };
+        Some(TriangleMesh::with_attributes(
+            self.positions,
+            normals,
+            uvs,
+            self.indices,
+            self.material,
+        ))
+    }
+}
+
+#[derive(Debug, Default)]
+struct ObjData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f64; 2]>,
+}
+
+/// Parse the contents of a Wavefront .obj file.
+/// Material libraries and textures are resolved relative to base_dir.
+pub fn parse_obj(source: &str, base_dir: &Path) -> Result<HittableList, ObjError> {
+    let default_material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8)));
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut data = ObjData::default();
+    let mut meshes: Vec<MeshBuilder> = Vec::new();
+    let mut current = MeshBuilder::new(default_material.clone());
+
+    for (line_index, raw_line) in source.lines().enumerate() {
+        let line = line_index + 1;
+        let statement = raw_line.split('#').next().unwrap_or("").trim();
+        let mut tokens = statement.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        match keyword {
+            "v" => data
+                .positions
+                .push(parse_vec3(&mut tokens, line, "vertex coordinate")?),
+            "vn" => data
+                .normals
+                .push(parse_vec3(&mut tokens, line, "normal coordinate")?),
+            "vt" => {
+                let u = parse_f64(tokens.next(), line, "texture coordinate")?;
+                let v = match tokens.next() {
+                    Some(token) => parse_f64(Some(token), line, "texture coordinate")?,
+                    None => 0.,
+                };
+                data.uvs.push([u, v]);
+            }
+            "f" => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let v = resolve_index(
+                        parts.next().unwrap_or(""),
+                        data.positions.len(),
+                        line,
+                        "vertex",
+                    )?;
+                    let vt = match parts.next() {
+                        Some("") | None => None,
+                        Some(vt) => Some(resolve_index(vt, data.uvs.len(), line, "texture")?),
+                    };
+                    let vn = match parts.next() {
+                        Some("") | None => None,
+                        Some(vn) => Some(resolve_index(vn, data.normals.len(), line, "normal")?),
+                    };
+                    face.push(current.vertex((v, vt, vn), &data));
+                }
+                if face.len() < 3 {
+                    return Err(parse_error(line, "a face needs at least 3 vertices"));
+                }
+                // Triangulate n-gons as a fan around the first vertex
+                for i in 1..face.len() - 1 {
+                    current.indices.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                let material = current.material.clone();
+                meshes.push(std::mem::replace(&mut current, MeshBuilder::new(material)));
+            }
+            "usemtl" => {
+                let name = remainder(statement, keyword, line)?;
+                let material = materials
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| default_material.clone());
+                meshes.push(std::mem::replace(&mut current, MeshBuilder::new(material)));
+            }
+            // One or more libraries, later ones overriding materials of the same name
+            "mtllib" => {
+                for name in remainder(statement, keyword, line)?.split_whitespace() {
+                    materials.extend(load_mtl(base_dir.join(name))?);
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are not supported
+            _ => {}
+        }
+    }
+    meshes.push(current);
+
+    let mut world = HittableList::new();
+    for mesh in meshes.into_iter().filter_map(MeshBuilder::build) {
+        world.add(Arc::new(mesh));
+    }
+    Ok(world)
+}
+
+#[derive(Debug)]
+struct MaterialDescription {
+    diffuse: Color,
+    diffuse_map: Option<PathBuf>,
+    specular: Color,
+    shininess: f64,
+    refraction_index: f64,
+    dissolve: f64,
+    illum: i32,
+}
+
+impl Default for MaterialDescription {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            diffuse_map: None,
+            specular: Color::default(),
+            shininess: 0.,
+            refraction_index: 1.5,
+            dissolve: 1.,
+            illum: 2,
+        }
+    }
+}
+
+impl MaterialDescription {
+    fn build(&self) -> Result<Arc<dyn Material>, ObjError> {
+        // Transparent materials, or the illumination models with refraction, become glass
+        if self.dissolve < 1. || matches!(self.illum, 4 | 6 | 7 | 9) {
+            return Ok(Arc::new(Dielectric::new(self.refraction_index)));
+        }
+        // Materials asking for reflections, or with only a specular color, become metals,
+        // textured or not. The Phong exponent is mapped to a fuzz where Ns = 0 is fully rough
+        let has_specular = !self.specular.near_zero();
+        if has_specular && (self.illum == 3 || self.diffuse.near_zero()) {
+            let fuzz = f64::sqrt(2. / (self.shininess.max(0.) + 2.)).min(1.);
+            return Ok(Arc::new(Metal::new(self.specular, fuzz)));
+        }
+        if let Some(path) = &self.diffuse_map {
+            // The texture is multiplied by Kd
+            let texture = ImageTexture::open(path, ColorSpace::Srgb).map_err(ObjError::Texture)?;
+            return Ok(Arc::new(Lambertian::with_texture(Arc::new(
+                Combine::multiply(Arc::new(texture), Arc::new(SolidColor::new(self.diffuse))),
+            ))));
+        }
+        Ok(Arc::new(Lambertian::new(self.diffuse)))
+    }
+}
+
+/// Parse the contents of a Wavefront .mtl material library.
+/// Texture maps are resolved relative to base_dir, and one that fails to load is an error.
+pub fn parse_mtl(
+    source: &str,
+    base_dir: &Path,
+) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MaterialDescription)> = None;
+
+    for (line_index, raw_line) in source.lines().enumerate() {
+        let line = line_index + 1;
+        let statement = raw_line.split('#').next().unwrap_or("").trim();
+        let mut tokens = statement.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            let name = remainder(statement, keyword, line)?.to_string();
+            if let Some((name, description)) = current.replace((name, Default::default())) {
+                materials.insert(name, description.build()?);
+            }
+            continue;
+        }
+
+        let Some((_, description)) = current.as_mut() else {
+            return Err(parse_error(
+                line,
+                format!("'{}' before any 'newmtl' statement", keyword),
+            ));
+        };
+        match keyword {
+            "Kd" => description.diffuse = parse_vec3(&mut tokens, line, "color component")?,
+            "Ks" => description.specular = parse_vec3(&mut tokens, line, "color component")?,
+            "Ns" => description.shininess = parse_f64(tokens.next(), line, "shininess")?,
+            "Ni" => description.refraction_index = parse_f64(tokens.next(), line, "index")?,
+            "d" => description.dissolve = parse_f64(tokens.next(), line, "dissolve")?,
+            "Tr" => description.dissolve = 1. - parse_f64(tokens.next(), line, "transparency")?,
+            "illum" => {
+                let token = tokens.next().unwrap_or("");
+                description.illum = token.parse().map_err(|_| {
+                    parse_error(line, format!("invalid illumination model '{}'", token))
+                })?;
+            }
+            "map_Kd" => {
+                // Texture options such as -s or -o come first, the file name is last
+                let file = tokens
+                    .last()
+                    .ok_or_else(|| parse_error(line, "'map_Kd' needs a file name"))?;
+                description.diffuse_map = Some(base_dir.join(file));
+            }
+            // Ambient and emissive colors and the other texture maps are not supported
+            _ => {}
+        }
+    }
+    if let Some((name, description)) = current {
+        materials.insert(name, description.build()?);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{HitRecord, Hittable},
+        interval::Interval,
+        ray::Ray,
+    };
+
+    const SQUARE: &str = "
+        # a unit square as a single quad face
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        g square
+        f 1/1/1 2/2/1 3/3/1 -1/-1/-1
+    ";
+
+    #[test]
+    fn parses_and_triangulates_quad() {
+        let world = parse_obj(SQUARE, Path::new("")).unwrap();
+        assert_eq!(world.objects.len(), 1);
+
+        let r = Ray::new(Point3::new(0.25, 0.75, 1.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!(f64::abs(rec.u - 0.25) < 1e-9);
+        assert!(f64::abs(rec.v - 0.75) < 1e-9);
+        assert_eq!(rec.normal, Vec3::new(0., 0., 1.));
+    }
+
+    #[test]
+    fn groups_become_separate_meshes() {
+        let source = "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            v 0 0 1
+            g first
+            f 1 2 3
+            g second
+            f 1 2 4
+        ";
+        let world = parse_obj(source, Path::new("")).unwrap();
+        assert_eq!(world.objects.len(), 2);
+    }
+
+    #[test]
+    fn out_of_range_index_reports_line() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n";
+        match parse_obj(source, Path::new("")) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 4),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_number_reports_line() {
+        let source = "v 0 0 0\nv 1 zero 0\n";
+        let err = parse_obj(source, Path::new("")).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid vertex coordinate 'zero'");
+    }
+
+    #[test]
+    fn missing_mtllib_is_io_error() {
+        let source = "mtllib does-not-exist.mtl\n";
+        assert!(matches!(
+            parse_obj(source, Path::new("")),
+            Err(ObjError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn mtllib_loads_every_library() {
+        let dir = std::env::temp_dir().join("rrtm_obj_mtllib_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("first.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+        std::fs::write(dir.join("second.mtl"), "newmtl blue\nKd 0 0 1\n").unwrap();
+        let source = format!("mtllib first.mtl second.mtl\nusemtl blue\n{}", SQUARE);
+        let world = parse_obj(&source, &dir).unwrap();
+
+        let r = Ray::new(Point3::new(0.25, 0.25, 1.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        let material = rec.material.clone().unwrap();
+        let srec = material.scatter(&r, &rec).unwrap();
+        assert_eq!(srec.attenuation, Color::new(0., 0., 1.));
+
+        // A missing library further down the line is still reported
+        let source = "mtllib first.mtl missing.mtl\n";
+        assert!(matches!(parse_obj(source, &dir), Err(ObjError::Io { .. })));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn maps_mtl_materials() {
+        let source = "
+            newmtl matte
+            Kd 0.5 0.2 0.1
+            newmtl chrome
+            Kd 0 0 0
+            Ks 0.9 0.9 0.9
+            Ns 500
+            newmtl glass
+            Ni 1.33
+            d 0.1
+        ";
+        let materials = parse_mtl(source, Path::new("")).unwrap();
+        // Scatter a ray hitting a surface facing it head on
+        let scatter = |name: &str| {
+            let r = Ray::new(Point3::new(0., 0., 1.), Vec3::new(0., 0., -1.));
+            let mut rec = HitRecord::default();
+            rec.set_face_normal(&r, &Vec3::new(0., 0., 1.));
+            materials[name].scatter(&r, &rec).unwrap()
+        };
+
+        // Diffuse surfaces scatter by a density, tinted by Kd
+        let matte = scatter("matte");
+        assert!(matte.specular_ray.is_none() && matte.pdf.is_some());
+        assert_eq!(matte.attenuation, Color::new(0.5, 0.2, 0.1));
+        // Metals reflect back up, tinted by Ks
+        let chrome = scatter("chrome");
+        assert!(chrome.specular_ray.unwrap().direction().z() > 0.);
+        assert_eq!(chrome.attenuation, Color::new(0.9, 0.9, 0.9));
+        // Glass follows a single direction without tinting
+        let glass = scatter("glass");
+        assert!(glass.specular_ray.is_some());
+        assert_eq!(glass.attenuation, Color::new(1., 1., 1.));
+    }
+
+    #[test]
+    fn textured_materials_keep_kd_and_ks() {
+        let dir = std::env::temp_dir().join("rrtm_obj_map_kd_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbImage::from_pixel(1, 1, image::Rgb([255, 255, 255]))
+            .save(dir.join("white.png"))
+            .unwrap();
+        let source = "
+            newmtl tinted
+            Kd 0.5 0.25 1
+            map_Kd white.png
+            newmtl shiny
+            Kd 0.5 0.25 1
+            Ks 0.7 0.7 0.7
+            illum 3
+            map_Kd white.png
+        ";
+        let materials = parse_mtl(source, &dir).unwrap();
+        let r = Ray::new(Point3::new(0., 0., 1.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        rec.set_face_normal(&r, &Vec3::new(0., 0., 1.));
+
+        // The white texture times Kd
+        let tinted = materials["tinted"].scatter(&r, &rec).unwrap();
+        assert!(tinted.pdf.is_some());
+        assert_eq!(tinted.attenuation, Color::new(0.5, 0.25, 1.));
+        // Reflections win over the texture like they do over Kd
+        let shiny = materials["shiny"].scatter(&r, &rec).unwrap();
+        assert!(shiny.specular_ray.is_some());
+        assert_eq!(shiny.attenuation, Color::new(0.7, 0.7, 0.7));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn missing_texture_map_is_error() {
+        let source = "newmtl broken\nmap_Kd does-not-exist.png\n";
+        assert!(matches!(
+            parse_mtl(source, Path::new("")),
+            Err(ObjError::Texture(TextureError::Io { .. }))
+        ));
+    }
+
+    #[test]
+    fn mtl_statement_before_newmtl_is_error() {
+        let err = parse_mtl("Kd 1 1 1\n", Path::new("")).unwrap_err();
+        assert!(matches!(err, ObjError::Parse { line: 1, .. }));
+    }
+}
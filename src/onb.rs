@@ -0,0 +1,84 @@
+use crate::vec3::{cross, dot, unit_vector, Vec3};
+
+// Orthonormal basis with w along a given direction, used to sample directions around a normal
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn new(n: &Vec3) -> Self {
+        let w = unit_vector(n);
+        // Any vector that is not parallel to w works to start the cross products
+        let a = if f64::abs(w.x()) > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let v = unit_vector(&cross(w, a));
+        let u = cross(w, v);
+        Self { axis: [u, v, w] }
+    }
+
+    // Basis around the normal n with u along the part of t perpendicular to it
+    pub fn with_tangent(n: &Vec3, t: &Vec3) -> Self {
+        let w = unit_vector(n);
+        let t = *t - w * dot(w, *t);
+        if t.near_zero() {
+            return Self::new(n);
+        }
+        let u = unit_vector(&t);
+        let v = cross(w, u);
+        Self { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    // Transform from basis coordinates to world coordinates
+    pub fn transform(&self, v: &Vec3) -> Vec3 {
+        self.axis[0] * v.x() + self.axis[1] * v.y() + self.axis[2] * v.z()
+    }
+
+    // Transform from world coordinates to basis coordinates
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            dot(*v, self.axis[0]),
+            dot(*v, self.axis[1]),
+            dot(*v, self.axis[2]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basis_is_orthonormal() {
+        for n in [
+            Vec3::new(0., 0., 1.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(-3., 2., 0.5),
+        ] {
+            let onb = Onb::new(&n);
+            assert!((onb.w() - unit_vector(&n)).near_zero());
+            assert!(f64::abs(dot(onb.u(), onb.v())) < 1e-12);
+            assert!(f64::abs(dot(onb.u(), onb.w())) < 1e-12);
+            assert!(f64::abs(dot(onb.v(), onb.w())) < 1e-12);
+            assert!(f64::abs(onb.u().length() - 1.) < 1e-12);
+            assert!(f64::abs(onb.v().length() - 1.) < 1e-12);
+            let z = onb.transform(&Vec3::new(0., 0., 1.));
+            assert!((z - onb.w()).near_zero());
+            let v = Vec3::new(0.3, -2., 1.);
+            assert!((onb.transform(&onb.to_local(&v)) - v).near_zero());
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{ImageFormat, Rgb32FImage, RgbImage};
+
+use crate::color::Color;
+
+// Writing rendered images to disk. The format is picked from the file extension: png, jpg,
+// jpeg and ppm are stored as 8 bit sRGB like the on screen image, while exr and pfm keep the
+// linear floating point values, highlights above 1 included, for compositing or tone mapping
+// later
+
+#[derive(Debug)]
+pub enum OutputError {
+    // The extension doesn't name a format we can write
+    UnsupportedFormat(PathBuf),
+    // The pixels don't fill a width by height image
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    // The file could not be written
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // The image encoder failed
+    Encode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::UnsupportedFormat(path) => write!(
+                f,
+                "unsupported output format for '{}', use png, jpg, ppm, exr or pfm",
+                path.display()
+            ),
+            OutputError::SizeMismatch { expected, actual } => {
+                write!(f, "expected {} pixels, got {}", expected, actual)
+            }
+            OutputError::Io { path, source } => {
+                write!(f, "could not write '{}': {}", path.display(), source)
+            }
+            OutputError::Encode { path, source } => {
+                write!(f, "could not encode '{}': {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OutputError::Io { source, .. } => Some(source),
+            OutputError::Encode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Whether write_image() knows the format of path, to check before spending time on a render
+pub fn is_supported(path: &Path) -> bool {
+    matches!(
+        extension(path).as_deref(),
+        Some("png" | "jpg" | "jpeg" | "ppm" | "exr" | "pfm")
+    )
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+}
+
+// Pixels are row by row from the top left, as returned by Camera::render()
+pub fn write_image(
+    path: &Path,
+    pixels: &[Color],
+    width: usize,
+    height: usize,
+) -> Result<(), OutputError> {
+    if pixels.len() != width * height {
+        return Err(OutputError::SizeMismatch {
+            expected: width * height,
+            actual: pixels.len(),
+        });
+    }
+    let encode_error = |source| OutputError::Encode {
+        path: path.to_path_buf(),
+        source,
+    };
+    match extension(path).as_deref() {
+        Some("png" | "jpg" | "jpeg" | "ppm") => {
+            let bytes = pixels.iter().flat_map(|p| p.get_rgb()).collect();
+            let image = RgbImage::from_raw(width as u32, height as u32, bytes).unwrap();
+            image.save(path).map_err(encode_error)
+        }
+        Some("exr") => {
+            let floats = pixels.iter().flat_map(linear_rgb).collect();
+            let image = Rgb32FImage::from_raw(width as u32, height as u32, floats).unwrap();
+            image
+                .save_with_format(path, ImageFormat::OpenExr)
+                .map_err(encode_error)
+        }
+        Some("pfm") => write_pfm(path, pixels, width, height).map_err(|source| OutputError::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+        _ => Err(OutputError::UnsupportedFormat(path.to_path_buf())),
+    }
+}
+
+// Float formats get the raw values, except for NaN and negative ones, which a few bad samples
+// can leave behind and which most viewers choke on
+fn linear_rgb(color: &Color) -> [f32; 3] {
+    [color.x(), color.y(), color.z()].map(|c| if c > 0. { c as f32 } else { 0. })
+}
+
+// Portable float map: a short text header, then little endian RGB floats with the bottom row
+// first
+fn write_pfm(path: &Path, pixels: &[Color], width: usize, height: usize) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    // A negative scale marks the data as little endian
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in pixels.chunks(width).rev() {
+        for pixel in row {
+            for c in linear_rgb(pixel) {
+                out.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Top row red and bright white, bottom row black and half grey
+    fn pixels() -> Vec<Color> {
+        vec![
+            Color::new(1., 0., 0.),
+            Color::new(4., 4., 4.),
+            Color::new(0., 0., 0.),
+            Color::new(0.5, 0.5, 0.5),
+        ]
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rrtm_output_test_{}", name))
+    }
+
+    #[test]
+    fn png_is_srgb_encoded() {
+        let path = temp_path("image.png");
+        write_image(&path, &pixels(), 2, 2).unwrap();
+        let image = image::open(&path).unwrap().into_rgb8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0]);
+        assert_eq!(image.get_pixel(1, 0).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(1, 1).0, Color::new(0.5, 0.5, 0.5).get_rgb());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn exr_keeps_linear_values() {
+        let path = temp_path("image.exr");
+        write_image(&path, &pixels(), 2, 2).unwrap();
+        let image = image::open(&path).unwrap().into_rgb32f();
+        assert_eq!(image.get_pixel(1, 0).0, [4., 4., 4.]);
+        assert_eq!(image.get_pixel(1, 1).0, [0.5, 0.5, 0.5]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn pfm_stores_bottom_row_first() {
+        let path = temp_path("image.pfm");
+        write_image(&path, &pixels(), 2, 2).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert!(bytes.starts_with(header));
+        let floats: Vec<f32> = bytes[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(floats.len(), 12);
+        assert_eq!(&floats[3..6], &[0.5, 0.5, 0.5]);
+        assert_eq!(&floats[9..12], &[4., 4., 4.]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn unknown_extensions_and_sizes_are_rejected() {
+        assert!(is_supported(Path::new("render.EXR")));
+        assert!(!is_supported(Path::new("render")));
+        assert!(matches!(
+            write_image(&temp_path("image.gif"), &pixels(), 2, 2),
+            Err(OutputError::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            write_image(&temp_path("image.png"), &pixels(), 3, 2),
+            Err(OutputError::SizeMismatch {
+                expected: 6,
+                actual: 4
+            })
+        ));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{f64::consts::PI, fmt::Debug, sync::Arc};
+
+use crate::{
+    hittable::Hittable,
+    onb::Onb,
+    ray::Point3,
+    utils::random_double,
+    vec3::{dot, unit_vector, Vec3},
+};
+
+// A distribution of directions that can be sampled and evaluated. Materials return one from
+// scatter() for the directions they prefer, and integrators can mix it with others, such as one
+// aimed at the lights
+pub trait Pdf: Send + Sync + Debug {
+    // Solid angle probability density of generate() returning the given direction
+    fn value(&self, direction: &Vec3) -> f64;
+    fn generate(&self) -> Vec3;
+}
+
+// Directions distributed as cos(theta) / pi around a normal, the ideal match for diffuse surfaces
+#[derive(Debug)]
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: &Vec3) -> Self {
+        Self { uvw: Onb::new(w) }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine_theta = dot(unit_vector(direction), self.uvw.w());
+        f64::max(0., cosine_theta / PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.uvw.transform(&Vec3::random_cosine_direction())
+    }
+}
+
+// Every direction equally likely
+#[derive(Debug, Default)]
+pub struct SpherePdf;
+
+impl SpherePdf {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        1. / (4. * PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        Vec3::random_unit_vector()
+    }
+}
+
+// Directions from a point towards the surface of some objects, usually the lights
+#[derive(Debug)]
+pub struct HittablePdf {
+    objects: Arc<dyn Hittable>,
+    origin: Point3,
+}
+
+impl HittablePdf {
+    pub fn new(objects: Arc<dyn Hittable>, origin: Point3) -> Self {
+        Self { objects, origin }
+    }
+}
+
+impl Pdf for HittablePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.objects.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.objects.random(&self.origin)
+    }
+}
+
+// Picks one of two distributions at random for every sample
+#[derive(Debug)]
+pub struct MixturePdf {
+    p: [Arc<dyn Pdf>; 2],
+    weight: f64, // probability of picking the first
+}
+
+impl MixturePdf {
+    // An even mix of both
+    pub fn new(p0: Arc<dyn Pdf>, p1: Arc<dyn Pdf>) -> Self {
+        Self::with_weight(p0, p1, 0.5)
+    }
+
+    pub fn with_weight(p0: Arc<dyn Pdf>, p1: Arc<dyn Pdf>, weight: f64) -> Self {
+        Self {
+            p: [p0, p1],
+            weight: weight.clamp(0., 1.),
+        }
+    }
+}
+
+impl Pdf for MixturePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.weight * self.p[0].value(direction) + (1. - self.weight) * self.p[1].value(direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        if random_double() < self.weight {
+            self.p[0].generate()
+        } else {
+            self.p[1].generate()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Monte Carlo estimate of the integral of pdf over all directions, which must be one
+    fn integral(pdf: &dyn Pdf) -> f64 {
+        let samples = 100_000;
+        let sphere = SpherePdf::new();
+        let sum: f64 = (0..samples)
+            .map(|_| {
+                let d = sphere.generate();
+                pdf.value(&d) / sphere.value(&d)
+            })
+            .sum();
+        sum / samples as f64
+    }
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let cosine = Arc::new(CosinePdf::new(&Vec3::new(1., 2., 3.)));
+        assert!(f64::abs(integral(cosine.as_ref()) - 1.) < 0.02);
+        let mixture = MixturePdf::with_weight(cosine, Arc::new(SpherePdf::new()), 0.3);
+        assert!(f64::abs(integral(&mixture) - 1.) < 0.02);
+    }
+
+    #[test]
+    fn cosine_samples_stay_above_surface() {
+        let normal = Vec3::new(0., -1., 0.);
+        let pdf = CosinePdf::new(&normal);
+        for _ in 0..1000 {
+            let d = pdf.generate();
+            assert!(dot(d, normal) >= 0.);
+            assert!(pdf.value(&d) >= 0.);
+        }
+    }
+}
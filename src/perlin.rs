@@ -1,58 +1,158 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
 use crate::{
     ray::Point3,
-    utils::{random_double, random_int},
+    vec3::{dot, unit_vector, Vec3},
 };
 
 const POINT_COUNT: usize = 256;
 
+// Gradient noise: every point of the integer lattice gets a random unit gradient, and the noise
+// at a point blends the gradients of the 8 lattice points around it. It is zero on the lattice
+// itself and varies smoothly in between, within about [-1, 1]
 #[derive(Debug)]
 pub struct Perlin {
-    randfloat: [f64; POINT_COUNT],
-    perm_x: [i32; POINT_COUNT],
-    perm_y: [i32; POINT_COUNT],
-    perm_z: [i32; POINT_COUNT],
+    randvec: [Vec3; POINT_COUNT],
+    perm_x: [usize; POINT_COUNT],
+    perm_y: [usize; POINT_COUNT],
+    perm_z: [usize; POINT_COUNT],
 }
 
 impl Perlin {
     pub fn new() -> Self {
-        let mut randfloat = [0.; POINT_COUNT];
-        for i in 0..POINT_COUNT {
-            randfloat[i] = random_double()
-        }
-        let mut perm_x = [0; POINT_COUNT];
-        let mut perm_y = [0; POINT_COUNT];
-        let mut perm_z = [0; POINT_COUNT];
-        Self::generate_perm(&mut perm_x);
-        Self::generate_perm(&mut perm_y);
-        Self::generate_perm(&mut perm_z);
+        Self::with_rng(&mut rand::thread_rng())
+    }
+
+    // The same seed always gives the same noise, so procedural scenes render the same each time
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(&mut StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(rng: &mut impl Rng) -> Self {
+        let randvec = [(); POINT_COUNT].map(|_| loop {
+            let v = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            // Uniform in the unit ball, so the gradients point in uniformly random directions
+            if v.length_squared() > 1e-12 && v.length_squared() <= 1. {
+                break unit_vector(&v);
+            }
+        });
         Self {
-            randfloat,
-            perm_x,
-            perm_y,
-            perm_z,
+            randvec,
+            perm_x: Self::generate_perm(rng),
+            perm_y: Self::generate_perm(rng),
+            perm_z: Self::generate_perm(rng),
         }
     }
-    fn generate_perm(p: &mut [i32]) {
-        for i in 0..POINT_COUNT {
-            p[i] = i as i32;
+
+    fn generate_perm(rng: &mut impl Rng) -> [usize; POINT_COUNT] {
+        let mut p: [usize; POINT_COUNT] = std::array::from_fn(|i| i);
+        for i in (1..POINT_COUNT).rev() {
+            let target = rng.gen_range(0..=i);
+            p.swap(i, target);
         }
-        Self::permute(p, POINT_COUNT);
+        p
     }
 
-    fn permute(p: &mut [i32], n: usize) {
-        for i in (0..n).rev() {
-            let target = random_int(0, i as i32) as usize;
-            let tmp = p[i];
-            p[i] = p[target];
-            p[target] = tmp;
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (u, v, w) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // The & 255 wraps the lattice around every 256 units, negative coordinates included
+        let mut c = [[[Vec3::default(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, gradient) in row.iter_mut().enumerate() {
+                    *gradient = self.randvec[self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize]];
+                }
+            }
         }
+        Self::perlin_interp(&c, u, v, w)
+    }
+
+    // Sum of depth octaves of noise, each twice the frequency and half the weight of the one
+    // before. The absolute value gives the creases that look like turbulent flow
+    pub fn turb(&self, p: &Point3, depth: usize) -> f64 {
+        let mut accum = 0.;
+        let mut temp_p = *p;
+        let mut weight = 1.;
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p *= 2.;
+        }
+        f64::abs(accum)
     }
-    pub fn noise(&self, p: &Point3) -> f64 {
-        // Don't fully understand the mechanics here with the &
-        let i = ((4. * p.x()) as i32 & 255) as usize;
-        let j = ((4. * p.y()) as i32 & 255) as usize;
-        let k = ((4. * p.z()) as i32 & 255) as usize;
 
-        return self.randfloat[(self.perm_x[i] ^ self.perm_y[j] ^ self.perm_z[k]) as usize];
+    // Trilinear blend of the lattice gradients dotted with the offset to each corner. The
+    // Hermite cubic on the weights keeps the result smooth across cell boundaries
+    fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        let uu = u * u * (3. - 2. * u);
+        let vv = v * v * (3. - 2. * v);
+        let ww = w * w * (3. - 2. * w);
+
+        let mut accum = 0.;
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, gradient) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight_v = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1. - fi) * (1. - uu))
+                        * (fj * vv + (1. - fj) * (1. - vv))
+                        * (fk * ww + (1. - fk) * (1. - ww))
+                        * dot(*gradient, weight_v);
+                }
+            }
+        }
+        accum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_noise_is_deterministic() {
+        let p = Point3::new(1.3, -2.7, 0.4);
+        let a = Perlin::with_seed(7);
+        let b = Perlin::with_seed(7);
+        let c = Perlin::with_seed(8);
+        assert_eq!(a.noise(&p), b.noise(&p));
+        assert_ne!(a.noise(&p), c.noise(&p));
+    }
+
+    #[test]
+    fn noise_is_smooth_and_zero_on_the_lattice() {
+        let perlin = Perlin::with_seed(1);
+        assert_eq!(perlin.noise(&Point3::new(3., -5., 12.)), 0.);
+        let mut min: f64 = 0.;
+        let mut max: f64 = 0.;
+        for i in 0..10_000 {
+            let p = Point3::new(i as f64 * 0.0173, i as f64 * -0.0291, i as f64 * 0.0057);
+            let n = perlin.noise(&p);
+            // Small steps only move the noise a little
+            let step = perlin.noise(&(p + Vec3::new(1e-4, 1e-4, 1e-4)));
+            assert!(f64::abs(n - step) < 1e-3);
+            min = min.min(n);
+            max = max.max(n);
+        }
+        assert!(min > -1.1 && max < 1.1);
+        assert!(min < -0.3 && max > 0.3);
+    }
+
+    #[test]
+    fn turbulence_is_positive() {
+        let perlin = Perlin::with_seed(2);
+        for i in 0..100 {
+            let p = Point3::new(i as f64 * 0.37, 0.5, -(i as f64) * 0.11);
+            assert!(perlin.turb(&p, 7) >= 0.);
+        }
     }
 }
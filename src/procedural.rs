@@ -0,0 +1,434 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use crate::{
+    color::Color,
+    hittable::HitRecord,
+    perlin::Perlin,
+    ray::Point3,
+    texture::{SolidColor, Texture},
+    vec3::{dot, Vec3},
+};
+
+// Solid textures computed from the hit point. Scalar patterns such as Worley, FBM and Gradient
+// come out as grey in [0, 1] and are meant to be colored through a ColorRamp, while patterns
+// with two materials such as Wood and Stripes blend two child textures, the way CheckerTexture
+// does with its even and odd ones
+
+// Which distance a Worley texture shows
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum WorleyFeature {
+    // To the nearest feature point, round cells that are dark in the middle
+    #[default]
+    F1,
+    // To the second nearest, angular cells
+    F2,
+    // Between the two, dark lines along the cell borders
+    F2MinusF1,
+}
+
+// Cellular noise: one randomly placed feature point in every unit cell of space
+#[derive(Debug)]
+pub struct Worley {
+    pub scale: f64,
+    pub feature: WorleyFeature,
+    pub seed: u64,
+}
+
+impl Worley {
+    pub fn new(scale: f64, feature: WorleyFeature) -> Self {
+        Self {
+            scale,
+            feature,
+            seed: 0,
+        }
+    }
+
+    // Feature point of the cell with the given lattice coordinates
+    fn feature_point(&self, cell: [i64; 3]) -> Point3 {
+        let mut h = self.seed;
+        for c in cell {
+            h = hash(h ^ c as u64);
+        }
+        let offset = [0, 1, 2].map(|i| {
+            h = hash(h.wrapping_add(i));
+            (h >> 11) as f64 / (1u64 << 53) as f64
+        });
+        Point3::new(
+            cell[0] as f64 + offset[0],
+            cell[1] as f64 + offset[1],
+            cell[2] as f64 + offset[2],
+        )
+    }
+
+    // Distances to the nearest and second nearest feature points, in cells
+    pub fn distances(&self, p: &Point3) -> (f64, f64) {
+        let p = *p * self.scale;
+        let cell = [p.x().floor(), p.y().floor(), p.z().floor()].map(|c| c as i64);
+        // Where p sits inside its cell
+        let inside = [
+            p.x() - cell[0] as f64,
+            p.y() - cell[1] as f64,
+            p.z() - cell[2] as f64,
+        ];
+        let (mut f1, mut f2) = (f64::INFINITY, f64::INFINITY);
+        // The point of p's own cell is never further than the cell diagonal, sqrt(3), so the
+        // nearest point is always within two cells of p. The second nearest is too unless f2
+        // is above 2, which takes an unusually empty neighbourhood. Cells that can't hold a
+        // point closer than the second nearest so far are skipped
+        for dx in -2..=2 {
+            for dy in -2..=2 {
+                for dz in -2..=2 {
+                    // Squared distance from p to the closest corner or side of the cell
+                    let gap = [dx, dy, dz]
+                        .iter()
+                        .zip(inside)
+                        .map(|(&d, x)| f64::max(0., f64::max(d as f64 - x, x - d as f64 - 1.)))
+                        .map(|g| g * g)
+                        .sum::<f64>();
+                    if gap >= f2 * f2 {
+                        continue;
+                    }
+                    let neighbour = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                    let d = (self.feature_point(neighbour) - p).length();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+
+// SplitMix64 finalizer, spreads every input bit over the whole output
+fn hash(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Texture for Worley {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let (f1, f2) = self.distances(p);
+        let d = match self.feature {
+            WorleyFeature::F1 => f1,
+            WorleyFeature::F2 => f2,
+            WorleyFeature::F2MinusF1 => f2 - f1,
+        };
+        Color::new(1., 1., 1.) * d.clamp(0., 1.)
+    }
+}
+
+// Fractal Brownian motion: octaves of Perlin noise, each lacunarity times the frequency and gain
+// times the weight of the one before. Unlike turbulence the octaves keep their sign, giving
+// cloud like softness rather than creases
+#[derive(Debug)]
+pub struct Fbm {
+    noise: Perlin,
+    pub scale: f64,
+    pub octaves: usize,
+    pub lacunarity: f64,
+    pub gain: f64,
+}
+
+impl Fbm {
+    pub fn new(scale: f64, octaves: usize) -> Self {
+        Self::with_noise(Perlin::new(), scale, octaves)
+    }
+
+    pub fn with_noise(noise: Perlin, scale: f64, octaves: usize) -> Self {
+        Self {
+            noise,
+            scale,
+            octaves,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+
+    // Sum of the octaves, normalized by the total weight to about [-1, 1]
+    pub fn fbm(&self, p: &Point3) -> f64 {
+        let mut p = *p * self.scale;
+        let (mut accum, mut weight, mut total) = (0., 1., 0.);
+        for _ in 0..self.octaves {
+            accum += weight * self.noise.noise(&p);
+            total += weight;
+            weight *= self.gain;
+            p *= self.lacunarity;
+        }
+        if total > 0. {
+            accum / total
+        } else {
+            0.
+        }
+    }
+}
+
+impl Texture for Fbm {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        Color::new(1., 1., 1.) * (0.5 * (1. + self.fbm(p))).clamp(0., 1.)
+    }
+}
+
+// Linear ramp from 0 at origin to 1 at origin + direction, clamped on either side
+#[derive(Debug)]
+pub struct Gradient {
+    origin: Point3,
+    direction: Vec3,
+}
+
+impl Gradient {
+    pub fn new(origin: Point3, direction: Vec3) -> Self {
+        Self { origin, direction }
+    }
+}
+
+impl Texture for Gradient {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let length_squared = self.direction.length_squared();
+        if length_squared == 0. {
+            return Color::default();
+        }
+        let t = dot(*p - self.origin, self.direction) / length_squared;
+        Color::new(1., 1., 1.) * t.clamp(0., 1.)
+    }
+}
+
+// Maps the first channel of a scalar texture to colors. Values between two stops are blended,
+// values past either end take the color of the end stop
+#[derive(Debug)]
+pub struct ColorRamp {
+    input: Arc<dyn Texture>,
+    stops: Vec<(f64, Color)>,
+}
+
+impl ColorRamp {
+    pub fn new(input: Arc<dyn Texture>, mut stops: Vec<(f64, Color)>) -> Self {
+        assert!(!stops.is_empty(), "a color ramp needs at least one stop");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { input, stops }
+    }
+
+    pub fn color_at(&self, t: f64) -> Color {
+        let i = self.stops.partition_point(|(position, _)| *position <= t);
+        if i == 0 {
+            return self.stops[0].1;
+        }
+        if i == self.stops.len() {
+            return self.stops[i - 1].1;
+        }
+        let (t0, c0) = self.stops[i - 1];
+        let (t1, c1) = self.stops[i];
+        c0 + (c1 - c0) * ((t - t0) / (t1 - t0))
+    }
+}
+
+impl Texture for ColorRamp {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.color_at(self.input.value(u, v, p).x())
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.color_at(self.input.sample(rec).x())
+    }
+}
+
+// Rings of growth around the y axis, wobbled by turbulence so they aren't perfect circles
+#[derive(Debug)]
+pub struct Wood {
+    noise: Perlin,
+    pub rings: f64,          // rings per unit of distance from the axis
+    pub wobble: f64,         // how far turbulence shifts the rings, in rings
+    pub grain: f64,          // frequency of the turbulence
+    light: Arc<dyn Texture>, // between the rings
+    dark: Arc<dyn Texture>,  // in the rings
+}
+
+impl Wood {
+    pub fn new(rings: f64, light: Arc<dyn Texture>, dark: Arc<dyn Texture>) -> Self {
+        Self::with_noise(Perlin::new(), rings, light, dark)
+    }
+
+    pub fn with_noise(
+        noise: Perlin,
+        rings: f64,
+        light: Arc<dyn Texture>,
+        dark: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            noise,
+            rings,
+            wobble: 1.,
+            grain: 2.,
+            light,
+            dark,
+        }
+    }
+
+    pub fn with_color(rings: f64, light: &Color, dark: &Color) -> Self {
+        Self::new(
+            rings,
+            Arc::new(SolidColor::new(*light)),
+            Arc::new(SolidColor::new(*dark)),
+        )
+    }
+
+    // How far into a ring p is, 0 between the rings and 1 in the middle of one
+    fn ring(&self, p: &Point3) -> f64 {
+        let radius = f64::sqrt(p.x() * p.x() + p.z() * p.z());
+        let ring = self.rings * radius + self.wobble * self.noise.turb(&(*p * self.grain), 4);
+        // Smooth transition into each ring and out of it again
+        0.5 * (1. - f64::cos(2. * PI * ring.fract()))
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let light = self.light.value(u, v, p);
+        light + (self.dark.value(u, v, p) - light) * self.ring(p)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        let light = self.light.sample(rec);
+        light + (self.dark.sample(rec) - light) * self.ring(&rec.p)
+    }
+}
+
+// Parallel bands across direction, alternating between two textures. The length of direction
+// is the number of stripe pairs per unit
+#[derive(Debug)]
+pub struct Stripes {
+    direction: Vec3,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl Stripes {
+    pub fn new(direction: Vec3, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        Self {
+            direction,
+            even,
+            odd,
+        }
+    }
+
+    pub fn with_color(direction: Vec3, c1: &Color, c2: &Color) -> Self {
+        Self::new(
+            direction,
+            Arc::new(SolidColor::new(*c1)),
+            Arc::new(SolidColor::new(*c2)),
+        )
+    }
+
+    fn pick(&self, p: &Point3) -> &Arc<dyn Texture> {
+        let position = dot(*p, self.direction);
+        match (position - position.floor()) < 0.5 {
+            true => &self.even,
+            false => &self.odd,
+        }
+    }
+}
+
+impl Texture for Stripes {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.pick(p).value(u, v, p)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.pick(&rec.p).sample(rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worley_distances_are_ordered() {
+        let worley = Worley::new(3., WorleyFeature::F1);
+        for i in 0..1000 {
+            let p = Point3::new(i as f64 * 0.013, -(i as f64) * 0.007, i as f64 * 0.021);
+            let (f1, f2) = worley.distances(&p);
+            assert!(f1 <= f2);
+            // A feature point in the same cell is never further than the cell diagonal
+            assert!(f1 <= f64::sqrt(3.));
+        }
+        // On a feature point the nearest distance is zero
+        let feature = worley.feature_point([2, -1, 5]) / 3.;
+        assert!(worley.distances(&feature).0 < 1e-9);
+    }
+
+    #[test]
+    fn worley_matches_a_wide_search() {
+        let worley = Worley::new(1., WorleyFeature::F1);
+        for i in 0..1000 {
+            let p = Point3::new(i as f64 * 0.131, -(i as f64) * 0.077, i as f64 * 0.053);
+            let cell = [p.x(), p.y(), p.z()].map(|c| c.floor() as i64);
+            let mut d: Vec<f64> = (-3..=3)
+                .flat_map(|dx| (-3..=3).flat_map(move |dy| (-3..=3).map(move |dz| [dx, dy, dz])))
+                .map(|o| {
+                    let neighbour = [cell[0] + o[0], cell[1] + o[1], cell[2] + o[2]];
+                    (worley.feature_point(neighbour) - p).length()
+                })
+                .collect();
+            d.sort_by(f64::total_cmp);
+            assert_eq!(worley.distances(&p), (d[0], d[1]));
+        }
+    }
+
+    #[test]
+    fn color_ramp_blends_and_clamps() {
+        let ramp = ColorRamp::new(
+            Arc::new(SolidColor::with_rgb(0., 0., 0.)),
+            vec![
+                (1., Color::new(0., 0., 1.)),
+                (0., Color::new(1., 0., 0.)),
+                (0.5, Color::new(0., 1., 0.)),
+            ],
+        );
+        assert_eq!(ramp.color_at(-1.), Color::new(1., 0., 0.));
+        assert_eq!(ramp.color_at(0.25), Color::new(0.5, 0.5, 0.));
+        assert_eq!(ramp.color_at(0.5), Color::new(0., 1., 0.));
+        assert_eq!(ramp.color_at(2.), Color::new(0., 0., 1.));
+        assert_eq!(
+            ramp.value(0., 0., &Point3::default()),
+            Color::new(1., 0., 0.)
+        );
+    }
+
+    #[test]
+    fn stripes_alternate() {
+        let black = Color::new(0., 0., 0.);
+        let white = Color::new(1., 1., 1.);
+        let stripes = Stripes::with_color(Vec3::new(2., 0., 0.), &black, &white);
+        let at = |x: f64| stripes.value(0., 0., &Point3::new(x, 7., -3.));
+        assert_eq!(at(0.1), black);
+        assert_eq!(at(0.3), white);
+        assert_eq!(at(0.6), black);
+        assert_eq!(at(-0.1), white);
+    }
+
+    #[test]
+    fn scalar_patterns_stay_in_range() {
+        let fbm = Fbm::with_noise(Perlin::with_seed(3), 2., 6);
+        let gradient = Gradient::new(Point3::default(), Vec3::new(0., 2., 0.));
+        let wood = Wood::with_noise(
+            Perlin::with_seed(4),
+            4.,
+            Arc::new(SolidColor::with_rgb(1., 1., 1.)),
+            Arc::new(SolidColor::with_rgb(0., 0., 0.)),
+        );
+        for i in 0..1000 {
+            let p = Point3::new(i as f64 * 0.031, i as f64 * 0.011 - 3., -(i as f64) * 0.017);
+            for texture in [&fbm as &dyn Texture, &gradient, &wood] {
+                let c = texture.value(0., 0., &p).x();
+                assert!((0. ..=1.).contains(&c));
+            }
+        }
+        assert_eq!(gradient.value(0., 0., &Point3::new(5., 1., 5.)).x(), 0.5);
+    }
+}
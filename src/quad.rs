@@ -0,0 +1,228 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    hittable::{hit_segment_end, HitRecord, Hittable, HittableList},
+    interval::Interval,
+    material::Material,
+    ray::{Point3, Ray},
+    utils::random_double,
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+
+#[derive(Debug)]
+pub struct Quad {
+    q: Point3, // starting corner of the quad
+    u: Vec3,   // first edge vector
+    v: Vec3,   // second edge vector
+    w: Vec3,   // cached n / (n . n), used to find the planar coordinates of a hit point
+    material: Option<Arc<dyn Material>>,
+    bbox: AABB,
+    normal: Vec3,
+    d: f64,    // plane equation constant, Ax + By + Cz = D
+    area: f64, // used to turn the uniform density over the surface into a solid angle density
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = cross(u, v);
+        let normal = unit_vector(&n);
+        let d = dot(normal, q);
+        let w = n / dot(n, n);
+        let area = n.length();
+
+        // Compute the bounding box of all four vertices. A quad lying on an axis-aligned plane
+        // has a zero-thickness box, so it is padded to keep the slab test well defined
+        let bbox_diagonal1 = AABB::with_points(&q, &(q + u + v));
+        let bbox_diagonal2 = AABB::with_points(&(q + u), &(q + v));
+        let bbox = AABB::with_boxes(&bbox_diagonal1, &bbox_diagonal2).pad_to_minimums();
+
+        Self {
+            q,
+            u,
+            v,
+            w,
+            material: Some(material),
+            bbox,
+            normal,
+            d,
+            area,
+        }
+    }
+
+    /// Given the hit point in plane coordinates, return false if it is outside the primitive,
+    /// otherwise set the hit record UV coordinates and return true.
+    fn is_interior(a: f64, b: f64, rec: &mut HitRecord) -> bool {
+        let unit_interval = Interval::new(0., 1.);
+        if !unit_interval.contains(a) || !unit_interval.contains(b) {
+            return false;
+        }
+        rec.u = a;
+        rec.v = b;
+        true
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let denom = dot(self.normal, r.direction());
+
+        // No hit if the ray is parallel to the plane
+        if f64::abs(denom) < 1e-8 {
+            return false;
+        }
+
+        // Return false if the hit point parameter t is outside the ray interval
+        let t = (self.d - dot(self.normal, r.origin())) / denom;
+        if !ray_t.contains(t) {
+            return false;
+        }
+
+        // Determine if the hit point lies within the planar shape using its plane coordinates
+        let intersection = r.at(t);
+        let planar_hitpt_vector = intersection - self.q;
+        let alpha = dot(self.w, cross(planar_hitpt_vector, self.v));
+        let beta = dot(self.w, cross(self.u, planar_hitpt_vector));
+
+        if !Self::is_interior(alpha, beta, rec) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = intersection;
+        rec.material = self.material.clone();
+        rec.set_face_normal(r, &self.normal);
+        rec.set_tangent_frame(&self.u, &self.v);
+        true
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(*origin, *direction),
+            Interval::new(0.001, f64::INFINITY),
+            &mut rec,
+        ) {
+            return 0.;
+        }
+        // A point picked uniformly on the area covers a solid angle that grows with the squared
+        // distance and shrinks as the quad turns away from the origin
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = f64::abs(dot(*direction, rec.normal) / direction.length());
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let p = self.q + (self.u * random_double()) + (self.v * random_double());
+        p - *origin
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        let (a, b) = (random_double(), random_double());
+        let rec = HitRecord {
+            p: self.q + (self.u * a) + (self.v * b),
+            normal: self.normal,
+            material: self.material.clone(),
+            u: a,
+            v: b,
+            front_face: true,
+            ..Default::default()
+        };
+        Some((rec, 1. / self.area))
+    }
+
+    fn surface_pdf(&self, origin: &Point3, point: &Point3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !hit_segment_end(self, origin, point, &mut rec) {
+            return 0.;
+        }
+        1. / self.area
+    }
+}
+
+// Returns the 3D box (six sides) that contains the two opposite vertices a & b
+pub fn make_box(a: &Point3, b: &Point3, material: Arc<dyn Material>) -> Arc<HittableList> {
+    let mut sides = HittableList::new();
+
+    let min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+    let max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+
+    let dx = Vec3::new(max.x() - min.x(), 0., 0.);
+    let dy = Vec3::new(0., max.y() - min.y(), 0.);
+    let dz = Vec3::new(0., 0., max.z() - min.z());
+
+    let sides_corners = [
+        (Point3::new(min.x(), min.y(), max.z()), dx, dy), // front
+        (Point3::new(max.x(), min.y(), max.z()), -dz, dy), // right
+        (Point3::new(max.x(), min.y(), min.z()), -dx, dy), // back
+        (Point3::new(min.x(), min.y(), min.z()), dz, dy), // left
+        (Point3::new(min.x(), max.y(), max.z()), dx, -dz), // top
+        (Point3::new(min.x(), min.y(), min.z()), dx, dz), // bottom
+    ];
+    for (q, u, v) in sides_corners {
+        sides.add(Arc::new(Quad::new(q, u, v, material.clone())));
+    }
+    Arc::new(sides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian};
+
+    fn unit_quad() -> Quad {
+        Quad::new(
+            Point3::new(0., 0., 0.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 4., 0.),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )
+    }
+
+    #[test]
+    fn hit_sets_uv_and_normal() {
+        let quad = unit_quad();
+        let r = Ray::new(Point3::new(0.5, 3., 5.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(quad.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert_eq!(rec.t, 5.);
+        assert_eq!(rec.u, 0.25);
+        assert_eq!(rec.v, 0.75);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0., 0., 1.));
+    }
+
+    #[test]
+    fn miss_outside_edges() {
+        let quad = unit_quad();
+        let r = Ray::new(Point3::new(2.5, 1., 5.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(!quad.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+    }
+
+    #[test]
+    fn sampled_directions_hit_with_density() {
+        let quad = unit_quad();
+        let origin = Point3::new(1., 1., 3.);
+        for _ in 0..100 {
+            let direction = quad.random(&origin);
+            let mut rec = HitRecord::default();
+            let r = Ray::new(origin, direction);
+            assert!(quad.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+            // Area 8 seen straight on from distance 3 covers less than 8 / 9 steradians
+            assert!(quad.pdf_value(&origin, &direction) > 9. / 8.);
+        }
+        let away = Vec3::new(0., 0., 1.);
+        assert_eq!(quad.pdf_value(&origin, &away), 0.);
+    }
+
+    #[test]
+    fn planar_bbox_is_padded() {
+        let bbox = unit_quad().bounding_box();
+        assert!(bbox.axis_interval(2).size() > 0.);
+    }
+}
@@ -2,7 +2,7 @@ use crate::vec3::Vec3;
 
 pub type Point3 = Vec3;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
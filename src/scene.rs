@@ -1,7 +1,8 @@
 use std::sync::Arc;
 
 use crate::{
-    bvh::BVHNode,
+    background::Background,
+    bvh::{BVHNode, SplitMethod},
     camera::Camera,
     color::Color,
     hittable::{Hittable, HittableList},
@@ -28,6 +29,30 @@ struct CameraUpdate {
     vup: Option<[f64; 3]>,
     defocus_angle: Option<f64>,
     focus_dist: Option<f64>,
+    background: Option<BackgroundUpdate>,
+}
+
+// Backgrounds that can be described from JS, e.g. { "type": "solid", "color": [0, 0, 0] }
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum BackgroundUpdate {
+    Solid { color: [f64; 3] },
+    Gradient { horizon: [f64; 3], zenith: [f64; 3] },
+    Sky,
+}
+
+impl From<BackgroundUpdate> for Background {
+    fn from(update: BackgroundUpdate) -> Self {
+        let to_color = |c: [f64; 3]| Color::new(c[0], c[1], c[2]);
+        match update {
+            BackgroundUpdate::Solid { color } => Background::Solid(to_color(color)),
+            BackgroundUpdate::Gradient { horizon, zenith } => Background::Gradient {
+                horizon: to_color(horizon),
+                zenith: to_color(zenith),
+            },
+            BackgroundUpdate::Sky => Background::sky(),
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -118,7 +143,7 @@ impl Scene {
 
         let mat3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
         world.add(Arc::new(Sphere::new(Point3::new(-4., 1., 0.), 1., mat3)));
-        let bvh = BVHNode::new(&mut world) as Arc<dyn Hittable>;
+        let bvh = BVHNode::with_split_method(&mut world, SplitMethod::Sah);
 
         Self {
             image: vec![255; 4 * camera.image_width() * camera.image_height()],
@@ -192,6 +217,13 @@ impl Scene {
             .map(|arr| Vec3::new(arr[0], arr[1], arr[2]))
             .unwrap_or_else(|| self.camera.vup);
 
+        let background = camera_update
+            .background
+            .map(Background::from)
+            .unwrap_or_else(|| self.camera.background.clone());
+        let lights = self.camera.lights.clone();
+        let integrator = self.camera.integrator;
+
         self.camera = Camera::new(
             camera_update
                 .width
@@ -208,6 +240,19 @@ impl Scene {
                 .unwrap_or(self.camera.defocus_angle),
             camera_update.focus_dist.unwrap_or(self.camera.focus_dist),
         );
+        self.camera.background = background;
+        self.camera.lights = lights;
+        self.camera.integrator = integrator;
+
+        self.clear();
+        self.current_sample_count = 0;
+
+        Ok(())
+    }
+
+    pub fn set_background(&mut self, js_background: JsValue) -> Result<(), JsValue> {
+        let background: BackgroundUpdate = serde_wasm_bindgen::from_value(js_background)?;
+        self.camera.background = background.into();
 
         self.clear();
         self.current_sample_count = 0;
@@ -1,9 +1,11 @@
 use crate::{
     aabb::AABB,
-    hittable::{HitRecord, Hittable},
+    hittable::{hit_segment_end, HitRecord, Hittable},
     interval::Interval,
     material::Material,
+    onb::Onb,
     ray::{Point3, Ray},
+    utils::random_double,
     vec3::{dot, Vec3},
 };
 use std::{f64::consts::PI, sync::Arc};
@@ -57,6 +59,30 @@ impl Sphere {
         *u = phi / (2. * PI);
         *v = theta / PI;
     }
+
+    // Directions in which a point on the unit sphere moves as u and v of get_sphere() increase
+    fn sphere_derivatives(p: &Point3) -> (Vec3, Vec3) {
+        let sin_theta = f64::sqrt(p.x() * p.x() + p.z() * p.z());
+        let dpdu = Vec3::new(p.z(), 0., -p.x());
+        if sin_theta == 0. {
+            return (dpdu, Vec3::default());
+        }
+        let dpdv = Vec3::new(-p.x() * p.y(), sin_theta * sin_theta, -p.z() * p.y()) / sin_theta;
+        (dpdu, dpdv)
+    }
+
+    // Random direction inside the cone from a point at distance_squared from the center that
+    // just contains a sphere of the given radius, around the +Z axis
+    fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+        let r1 = random_double();
+        let r2 = random_double();
+        let z = 1. + r2 * (f64::sqrt(1. - radius * radius / distance_squared) - 1.);
+
+        let phi = 2. * PI * r1;
+        let x = f64::cos(phi) * f64::sqrt(1. - z * z);
+        let y = f64::sin(phi) * f64::sqrt(1. - z * z);
+        Vec3::new(x, y, z)
+    }
 }
 
 impl Hittable for Sphere {
@@ -91,12 +117,65 @@ impl Hittable for Sphere {
         let outward_normal = (rec.p - current_center) / self.radius;
         rec.set_face_normal(r, &outward_normal);
         Self::get_sphere(&outward_normal, &mut rec.u, &mut rec.v);
+        let (dpdu, dpdv) = Self::sphere_derivatives(&outward_normal);
+        // u goes around the whole circumference and v from pole to pole
+        rec.set_tangent_frame(
+            &(dpdu * (2. * PI * self.radius)),
+            &(dpdv * (PI * self.radius)),
+        );
         return true;
     }
 
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    // Lights are sampled through the cone of directions the sphere covers, which only works for
+    // points outside of it. Moving spheres are sampled at their starting position
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(*origin, *direction),
+            Interval::new(0.001, f64::INFINITY),
+            &mut rec,
+        ) {
+            return 0.;
+        }
+        let distance_squared = (self.center.at(0.) - *origin).length_squared();
+        let cos_theta_max = f64::sqrt(1. - self.radius * self.radius / distance_squared);
+        let solid_angle = 2. * PI * (1. - cos_theta_max);
+        1. / solid_angle
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let direction = self.center.at(0.) - *origin;
+        let distance_squared = direction.length_squared();
+        let local = Self::random_to_sphere(self.radius, distance_squared);
+
+        // Rotate the cone from around +Z to around the direction of the center
+        Onb::new(&direction).transform(&local)
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        let outward_normal = Vec3::random_unit_vector();
+        let mut rec = HitRecord {
+            p: self.center.at(0.) + outward_normal * self.radius,
+            normal: outward_normal,
+            material: self.material.clone(),
+            front_face: true,
+            ..Default::default()
+        };
+        Self::get_sphere(&outward_normal, &mut rec.u, &mut rec.v);
+        Some((rec, 1. / (4. * PI * self.radius * self.radius)))
+    }
+
+    fn surface_pdf(&self, origin: &Point3, point: &Point3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !hit_segment_end(self, origin, point, &mut rec) {
+            return 0.;
+        }
+        1. / (4. * PI * self.radius * self.radius)
+    }
 }
 
 pub fn hit_sphere_naive(center: &Point3, radius: f64, r: &Ray) -> f64 {
@@ -124,3 +203,75 @@ pub fn hit_sphere(center: &Point3, radius: f64, r: &Ray) -> f64 {
         return (h - f64::sqrt(discriminant)) / a;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian, vec3::unit_vector};
+
+    #[test]
+    fn sampled_directions_hit_sphere() {
+        let sphere = Sphere::new(
+            Point3::new(0., 5., 0.),
+            1.,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let origin = Point3::new(0., 0., 0.);
+        // Cone with sin(theta_max) = 1 / 5
+        let solid_angle = 2. * PI * (1. - f64::sqrt(24.) / 5.);
+        for _ in 0..100 {
+            let direction = sphere.random(&origin);
+            let pdf = sphere.pdf_value(&origin, &direction);
+            assert!(f64::abs(pdf - 1. / solid_angle) < 1e-9);
+        }
+        assert_eq!(sphere.pdf_value(&origin, &Vec3::new(0., -1., 0.)), 0.);
+    }
+
+    #[test]
+    fn tangent_frame_follows_uv() {
+        let sphere = Sphere::new(
+            Point3::new(0., 0., 0.),
+            2.,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        for direction in [Vec3::new(1., 0.3, 0.2), Vec3::new(-0.4, -0.6, 0.7)] {
+            let r = Ray::new(direction * 5., -direction);
+            let mut rec = HitRecord::default();
+            assert!(sphere.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+            assert!(f64::abs(dot(rec.tangent, rec.normal)) < 1e-9);
+            assert!(f64::abs(dot(rec.bitangent, rec.normal)) < 1e-9);
+
+            // Moving a little along the tangent and bitangent increases u and v
+            let (mut u, mut v) = (0., 0.);
+            Sphere::get_sphere(
+                &unit_vector(&(rec.normal + rec.tangent * 1e-3)),
+                &mut u,
+                &mut v,
+            );
+            assert!(u > rec.u);
+            Sphere::get_sphere(
+                &unit_vector(&(rec.normal + rec.bitangent * 1e-3)),
+                &mut u,
+                &mut v,
+            );
+            assert!(v > rec.v);
+        }
+    }
+
+    #[test]
+    fn uv_scale_matches_surface_size() {
+        let sphere = Sphere::new(
+            Point3::new(0., 0., 0.),
+            2.,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        // On the equator u runs around 4 pi and v over 2 pi units of distance
+        let r = Ray::new(Point3::new(5., 0., 0.), Vec3::new(-1., 0., 0.));
+        let mut rec = HitRecord::default();
+        assert!(sphere.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        let expected = 1. / f64::sqrt(4. * PI * 2. * PI);
+        assert!(f64::abs(rec.uv_per_unit - expected) < 1e-9);
+        rec.set_footprint(0.5);
+        assert!(f64::abs(rec.footprint - 0.5 * expected) < 1e-9);
+    }
+}
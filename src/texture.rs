@@ -1,14 +1,35 @@
-use image::{DynamicImage, GenericImageView, Rgb};
+use image::{DynamicImage, GenericImageView, ImageReader, Rgba};
 use std::{
-    fmt::Debug,
+    fmt::{self, Debug},
+    io,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use crate::{color::Color, perlin::Perlin, ray::Point3};
+use crate::{
+    color::{srgb_to_linear, Color},
+    hittable::HitRecord,
+    mipmap::{Filter, MipMap, WrapMode},
+    perlin::Perlin,
+    ray::Point3,
+};
 
 pub trait Texture: Send + Sync + Debug {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+
+    // Coverage in [0, 1], used to cut holes into surfaces. Opaque unless the texture has an
+    // alpha channel
+    fn alpha(&self, _u: f64, _v: f64, _p: &Point3) -> f64 {
+        1.
+    }
+
+    // Value at a hit, which materials use instead of value(). Besides u, v and p it has the
+    // normal and the footprint of the ray, so images can filter over the patch of surface the
+    // ray stands for and projections can pick an axis. Textures that compose others forward it
+    // to their children
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, &rec.p)
+    }
 }
 
 #[derive(Debug)]
@@ -57,127 +78,355 @@ impl CheckerTexture {
             Arc::new(SolidColor::new(*c2)),
         )
     }
-}
 
-impl Texture for CheckerTexture {
-    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+    fn pick(&self, p: &Point3) -> &Arc<dyn Texture> {
         let x = f64::floor(self.inv_scale * p.x()) as i32;
         let y = f64::floor(self.inv_scale * p.y()) as i32;
         let z = f64::floor(self.inv_scale * p.z()) as i32;
-        let is_even = (x + y + z) % 2 == 0;
-        match is_even {
-            true => self.even.value(u, v, p),
-            false => self.odd.value(u, v, p),
+        match (x + y + z) % 2 == 0 {
+            true => &self.even,
+            false => &self.odd,
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.pick(p).value(u, v, p)
+    }
+
+    fn alpha(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        self.pick(p).alpha(u, v, p)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.pick(&rec.p).sample(rec)
+    }
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    // The file could not be read
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // The data is not an image in a format we can decode. path is None for images decoded
+    // from memory
+    Decode {
+        path: Option<PathBuf>,
+        source: image::ImageError,
+    },
+    // The image decoded fine but has no pixels to sample
+    Empty,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Io { path, source } => {
+                write!(f, "could not read '{}': {}", path.display(), source)
+            }
+            TextureError::Decode {
+                path: Some(path),
+                source,
+            } => write!(f, "could not decode '{}': {}", path.display(), source),
+            TextureError::Decode { path: None, source } => {
+                write!(f, "could not decode image: {}", source)
+            }
+            TextureError::Empty => write!(f, "image has no pixels"),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TextureError::Io { source, .. } => Some(source),
+            TextureError::Decode { source, .. } => Some(source),
+            TextureError::Empty => None,
         }
     }
 }
 
 #[derive(Debug)]
 pub struct RTImage {
-    image: Option<DynamicImage>,
+    image: DynamicImage,
 }
 
 impl RTImage {
-    pub fn new(filename: &str) -> Self {
-        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("textures")
-            .join(filename);
-        let image = match image::open(path) {
-            Ok(img) => Some(img),
-            Err(e) => {
-                eprintln!("ERROR: Could not load image file '{}': {}", filename, e);
-                None
-            }
+    // The format is found from the contents of the file, falling back to its extension
+    pub fn open(path: &Path) -> Result<Self, TextureError> {
+        let io_error = |source| TextureError::Io {
+            path: path.to_path_buf(),
+            source,
         };
-        Self { image }
+        let image = ImageReader::open(path)
+            .map_err(io_error)?
+            .with_guessed_format()
+            .map_err(io_error)?
+            .decode()
+            .map_err(|source| TextureError::Decode {
+                path: Some(path.to_path_buf()),
+                source,
+            })?;
+        Self::with_image(image)
+    }
+
+    // Decodes an encoded image file held in memory, such as bytes handed over from JavaScript
+    // in the wasm build, where there is no file system to read from
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TextureError> {
+        let image = image::load_from_memory(bytes)
+            .map_err(|source| TextureError::Decode { path: None, source })?;
+        Self::with_image(image)
+    }
+
+    pub fn with_image(image: DynamicImage) -> Result<Self, TextureError> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err(TextureError::Empty);
+        }
+        Ok(Self { image })
     }
 
     pub fn width(&self) -> u32 {
-        self.image.as_ref().map_or(0, |img| img.width())
+        self.image.width()
     }
     pub fn height(&self) -> u32 {
-        self.image.as_ref().map_or(0, |img| img.height())
+        self.image.height()
     }
 
-    pub fn pixel_data(&self, x: u32, y: u32) -> Rgb<u8> {
-        match &self.image {
-            Some(img) => {
-                let x = x.min(self.width() - 1);
-                let y = y.min(self.height() - 1);
-
-                // Convert pixels to RGB
-                let pixel = img.get_pixel(x, y);
-                Rgb([pixel[0], pixel[1], pixel[2]])
-            }
-            None => Rgb([255, 0, 255]), // Magenta for error
-        }
+    // Images without an alpha channel come back fully opaque
+    pub fn pixel_data(&self, x: u32, y: u32) -> Rgba<u8> {
+        let x = x.min(self.width() - 1);
+        let y = y.min(self.height() - 1);
+        self.image.get_pixel(x, y)
     }
 
     pub fn get_linear_pixel(&self, x: u32, y: u32) -> [f64; 3] {
         let pixel = self.pixel_data(x, y);
-        [
-            pixel[0] as f64 / 255.,
-            pixel[1] as f64 / 255.,
-            pixel[2] as f64 / 255.,
-        ]
+        [0, 1, 2].map(|c| srgb_to_linear(pixel[c] as f64 / 255.))
+    }
+
+    // Every pixel as RGBA in [0, 1], row by row from the top left, still in the color space of
+    // the file
+    pub fn texels(&self) -> Vec<[f32; 4]> {
+        self.image
+            .to_rgba32f()
+            .pixels()
+            .map(|pixel| pixel.0)
+            .collect()
+    }
+}
+
+// How the color channels of an image are encoded. Alpha is always linear
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    // Gamma encoded for display, as almost all 8 bit color images are. Use for albedo and
+    // emission
+    #[default]
+    Srgb,
+    // Values stored as is, for data such as roughness, height and normal maps, and for float
+    // formats like HDR and EXR
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn decode(&self, value: f32) -> f32 {
+        match self {
+            ColorSpace::Srgb => srgb_to_linear(value as f64) as f32,
+            ColorSpace::Linear => value,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct ImageTexture {
-    image: RTImage, // Using the TextureImage we created earlier
+    texels: Option<MipMap>, // None for the placeholder
+    pub wrap: WrapMode,
+    pub filter: Filter,
 }
 
 impl ImageTexture {
-    pub fn new(filename: &str) -> Self {
-        ImageTexture {
-            image: RTImage::new(filename),
+    pub fn open(path: &Path, color_space: ColorSpace) -> Result<Self, TextureError> {
+        Ok(Self::with_image(&RTImage::open(path)?, color_space))
+    }
+
+    pub fn from_bytes(bytes: &[u8], color_space: ColorSpace) -> Result<Self, TextureError> {
+        Ok(Self::with_image(&RTImage::from_bytes(bytes)?, color_space))
+    }
+
+    // Opt-in fallback for when a broken texture should not stop the render: reports the error
+    // and returns the placeholder instead
+    pub fn open_or_placeholder(path: &Path, color_space: ColorSpace) -> Self {
+        Self::open(path, color_space).unwrap_or_else(|e| {
+            eprintln!("ERROR: {}", e);
+            Self::placeholder()
+        })
+    }
+
+    // Solid cyan, which stands out as a debugging aid
+    pub fn placeholder() -> Self {
+        Self {
+            texels: None,
+            wrap: WrapMode::default(),
+            filter: Filter::default(),
         }
     }
-}
 
-impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
-        // If we have no texture data, return solid cyan as debugging aid
-        if self.image.height() == 0 {
-            return Color::new(0.0, 1.0, 1.0);
+    // Decodes the image once into linear floats, so lookups only have to filter
+    pub fn with_image(image: &RTImage, color_space: ColorSpace) -> Self {
+        let texels = image
+            .texels()
+            .into_iter()
+            .map(|[r, g, b, a]| {
+                let [r, g, b] = [r, g, b].map(|c| color_space.decode(c));
+                [r, g, b, a]
+            })
+            .collect();
+        Self {
+            texels: Some(MipMap::new(
+                image.width() as usize,
+                image.height() as usize,
+                texels,
+            )),
+            wrap: WrapMode::default(),
+            filter: Filter::default(),
         }
+    }
 
-        // Clamp input texture coordinates to [0,1] x [1,0]
-        let u = u.clamp(0.0, 1.0);
-        let v = 1.0 - v.clamp(0.0, 1.0); // Flip V to image coordinates
+    fn texel(&self, u: f64, v: f64, footprint: f64) -> Option<[f32; 4]> {
+        let texels = self.texels.as_ref()?;
+        Some(texels.lookup(u, v, footprint, self.wrap, self.filter))
+    }
 
-        // Convert to integer pixel coordinates
-        let i = (u * self.image.width() as f64) as u32;
-        let j = (v * self.image.height() as f64) as u32;
+    // Color averaged over a patch footprint wide in uv units
+    pub fn filtered_value(&self, u: f64, v: f64, footprint: f64) -> Color {
+        match self.texel(u, v, footprint) {
+            Some(texel) => Color::new(texel[0] as f64, texel[1] as f64, texel[2] as f64),
+            None => Color::new(0.0, 1.0, 1.0),
+        }
+    }
+}
 
-        // Get pixel data and convert to color
-        let pixel = self.image.pixel_data(i, j);
-        const COLOR_SCALE: f64 = 1.0 / 255.0;
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        self.filtered_value(u, v, 0.)
+    }
 
-        Color::new(
-            pixel[0] as f64 * COLOR_SCALE,
-            pixel[1] as f64 * COLOR_SCALE,
-            pixel[2] as f64 * COLOR_SCALE,
-        )
+    fn alpha(&self, u: f64, v: f64, _p: &Point3) -> f64 {
+        self.texel(u, v, 0.).map_or(1., |texel| texel[3] as f64)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.filtered_value(rec.u, rec.v, rec.footprint)
     }
 }
 
+// How a NoiseTexture turns Perlin noise into a shade of grey
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum NoiseStyle {
+    // Plain noise mapped from [-1, 1] to [0, 1], soft blobs
+    #[default]
+    Smooth,
+    // Seven octaves of turbulence, a camouflage like pattern
+    Turbulence,
+    // Stripes along z, bent by turbulence, like the veins in marble
+    Marble,
+}
+
 #[derive(Debug)]
 pub struct NoiseTexture {
     noise: Perlin,
+    pub scale: f64, // frequency of the noise, higher is finer
+    pub style: NoiseStyle,
 }
 
 impl NoiseTexture {
     pub fn new() -> Self {
+        Self::with_scale(1.)
+    }
+
+    pub fn with_scale(scale: f64) -> Self {
+        Self::with_noise(Perlin::new(), scale, NoiseStyle::Smooth)
+    }
+
+    pub fn marble(scale: f64) -> Self {
+        Self::with_noise(Perlin::new(), scale, NoiseStyle::Marble)
+    }
+
+    // Pass a Perlin::with_seed() for noise that is the same on every render
+    pub fn with_noise(noise: Perlin, scale: f64, style: NoiseStyle) -> Self {
         Self {
-            noise: Perlin::new(),
+            noise,
+            scale,
+            style,
         }
     }
 }
 
+const TURBULENCE_DEPTH: usize = 7;
+
 impl Texture for NoiseTexture {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
-        return Color::new(1., 1., 1.) * self.noise.noise(p);
+        let shade = match self.style {
+            NoiseStyle::Smooth => 0.5 * (1. + self.noise.noise(&(*p * self.scale))),
+            NoiseStyle::Turbulence => self.noise.turb(&(*p * self.scale), TURBULENCE_DEPTH),
+            NoiseStyle::Marble => {
+                0.5 * (1.
+                    + f64::sin(self.scale * p.z() + 10. * self.noise.turb(p, TURBULENCE_DEPTH)))
+            }
+        };
+        return Color::new(1., 1., 1.) * shade;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_decoded_by_color_space() {
+        let path = std::env::temp_dir().join("rrtm_color_space_test.png");
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 128, 128]))
+            .save(&path)
+            .unwrap();
+        let image = RTImage::open(&path).unwrap();
+        let p = Point3::default();
+
+        let srgb = ImageTexture::with_image(&image, ColorSpace::Srgb);
+        let linear = ImageTexture::with_image(&image, ColorSpace::Linear);
+        assert!(f64::abs(srgb.value(0.5, 0.5, &p).x() - 0.2158) < 1e-3);
+        assert!(f64::abs(linear.value(0.5, 0.5, &p).x() - 128. / 255.) < 1e-6);
+        // Alpha is never gamma encoded
+        assert!(f64::abs(srgb.alpha(0.5, 0.5, &p) - 128. / 255.) < 1e-6);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn loading_errors_are_reported() {
+        let missing = Path::new("no/such/texture.png");
+        assert!(matches!(
+            ImageTexture::open(missing, ColorSpace::Srgb),
+            Err(TextureError::Io { .. })
+        ));
+        assert!(matches!(
+            ImageTexture::from_bytes(b"not an image", ColorSpace::Srgb),
+            Err(TextureError::Decode { path: None, .. })
+        ));
+        // The placeholder has to be asked for
+        let placeholder = ImageTexture::open_or_placeholder(missing, ColorSpace::Srgb);
+        let p = Point3::default();
+        assert_eq!(placeholder.value(0.5, 0.5, &p), Color::new(0., 1., 1.));
+    }
+
+    #[test]
+    fn images_load_from_bytes() {
+        let mut bytes = Vec::new();
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255]))
+            .write_to(&mut io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        let texture = ImageTexture::from_bytes(&bytes, ColorSpace::Srgb).unwrap();
+        let p = Point3::default();
+        assert_eq!(texture.value(0.3, 0.7, &p), Color::new(1., 0., 0.));
     }
 }
@@ -0,0 +1,427 @@
+use std::sync::Arc;
+
+use crate::{
+    color::Color,
+    hittable::HitRecord,
+    ray::Point3,
+    texture::{SolidColor, Texture},
+    utils::degrees_to_radians,
+    vec3::Vec3,
+};
+
+// Textures that only combine or reshape other textures, so looks can be built as small graphs
+// of Arc<dyn Texture> instead of new types. Each forwards sample() to its children, so images
+// further down the graph are still filtered and projections still see the normal, and alpha()
+// too, so cutouts still work through them
+
+// Blend from a to b by the first channel of mask, 0 giving a and 1 giving b
+#[derive(Debug)]
+pub struct Mix {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    mask: Arc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, mask: Arc<dyn Texture>) -> Self {
+        Self { a, b, mask }
+    }
+
+    pub fn with_factor(a: Arc<dyn Texture>, b: Arc<dyn Texture>, factor: f64) -> Self {
+        Self::new(a, b, Arc::new(SolidColor::with_rgb(factor, factor, factor)))
+    }
+
+    fn mix(a: Color, b: Color, mask: Color) -> Color {
+        a + (b - a) * mask.x().clamp(0., 1.)
+    }
+}
+
+impl Texture for Mix {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        Self::mix(
+            self.a.value(u, v, p),
+            self.b.value(u, v, p),
+            self.mask.value(u, v, p),
+        )
+    }
+
+    fn alpha(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        let t = self.mask.value(u, v, p).x().clamp(0., 1.);
+        let a = self.a.alpha(u, v, p);
+        a + (self.b.alpha(u, v, p) - a) * t
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        Self::mix(
+            self.a.sample(rec),
+            self.b.sample(rec),
+            self.mask.sample(rec),
+        )
+    }
+}
+
+// Channel by channel arithmetic on two textures
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+#[derive(Debug)]
+pub struct Combine {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    operation: Operation,
+}
+
+impl Combine {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, operation: Operation) -> Self {
+        Self { a, b, operation }
+    }
+
+    pub fn add(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Self {
+        Self::new(a, b, Operation::Add)
+    }
+
+    // Mostly used to tint or darken, such as an albedo times an ambient occlusion map
+    pub fn multiply(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Self {
+        Self::new(a, b, Operation::Multiply)
+    }
+
+    fn apply(&self, a: Color, b: Color) -> Color {
+        match self.operation {
+            Operation::Add => a + b,
+            Operation::Subtract => a - b,
+            Operation::Multiply => a * b,
+        }
+    }
+}
+
+impl Texture for Combine {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.apply(self.a.value(u, v, p), self.b.value(u, v, p))
+    }
+
+    // Solid only where both are, whatever the operation
+    fn alpha(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        self.a.alpha(u, v, p) * self.b.alpha(u, v, p)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.apply(self.a.sample(rec), self.b.sample(rec))
+    }
+}
+
+// Reads a texture at transformed coordinates: (u, v) is scaled, then rotated counterclockwise
+// around the origin, then offset. A scale of 4 repeats the texture 4 times across the surface,
+// given a wrapping ImageTexture
+#[derive(Debug)]
+pub struct UvTransform {
+    texture: Arc<dyn Texture>,
+    matrix: [[f64; 2]; 2],
+    offset: [f64; 2],
+}
+
+impl UvTransform {
+    pub fn new(texture: Arc<dyn Texture>, scale: [f64; 2], degrees: f64, offset: [f64; 2]) -> Self {
+        let (sin, cos) = f64::sin_cos(degrees_to_radians(degrees));
+        Self {
+            texture,
+            matrix: [
+                [cos * scale[0], -sin * scale[1]],
+                [sin * scale[0], cos * scale[1]],
+            ],
+            offset,
+        }
+    }
+
+    pub fn scale(texture: Arc<dyn Texture>, su: f64, sv: f64) -> Self {
+        Self::new(texture, [su, sv], 0., [0., 0.])
+    }
+
+    fn transform(&self, u: f64, v: f64) -> (f64, f64) {
+        let m = &self.matrix;
+        (
+            m[0][0] * u + m[0][1] * v + self.offset[0],
+            m[1][0] * u + m[1][1] * v + self.offset[1],
+        )
+    }
+}
+
+impl Texture for UvTransform {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let (u, v) = self.transform(u, v);
+        self.texture.value(u, v, p)
+    }
+
+    fn alpha(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        let (u, v) = self.transform(u, v);
+        self.texture.alpha(u, v, p)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        let mut rec = rec.clone();
+        (rec.u, rec.v) = self.transform(rec.u, rec.v);
+        // Areas grow by the determinant, widths by its square root
+        let m = &self.matrix;
+        let stretch = f64::sqrt(f64::abs(m[0][0] * m[1][1] - m[0][1] * m[1][0]));
+        rec.footprint *= stretch;
+        rec.uv_per_unit *= stretch;
+        self.texture.sample(&rec)
+    }
+}
+
+// Projects a texture onto the surface along each of the x, y and z axes, with p times scale as
+// the coordinates, and blends the three by how squarely the normal faces each axis. Covers
+// shapes without a usable parameterization, such as boxes and meshes without uvs, without
+// visible stretching. Higher sharpness gives narrower blends at the edges. value() has no
+// normal to go by and gives an even blend
+#[derive(Debug)]
+pub struct Triplanar {
+    texture: Arc<dyn Texture>,
+    pub scale: f64,
+    pub sharpness: f64,
+}
+
+impl Triplanar {
+    pub fn new(texture: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            texture,
+            scale,
+            sharpness: 4.,
+        }
+    }
+
+    // Coordinates of p projected along each axis, in the order x, y, z
+    fn projections(&self, p: &Point3) -> [(f64, f64); 3] {
+        let p = *p * self.scale;
+        [(p.z(), p.y()), (p.x(), p.z()), (p.x(), p.y())]
+    }
+
+    fn weights(&self, normal: &Vec3) -> [f64; 3] {
+        let w = [normal.x(), normal.y(), normal.z()].map(|c| f64::abs(c).powf(self.sharpness));
+        let total = w[0] + w[1] + w[2];
+        if total > 0. {
+            w.map(|w| w / total)
+        } else {
+            [1. / 3.; 3]
+        }
+    }
+}
+
+impl Texture for Triplanar {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        self.projections(p)
+            .iter()
+            .fold(Color::default(), |sum, (u, v)| {
+                sum + self.texture.value(*u, *v, p) / 3.
+            })
+    }
+
+    fn alpha(&self, _u: f64, _v: f64, p: &Point3) -> f64 {
+        self.projections(p)
+            .iter()
+            .map(|(u, v)| self.texture.alpha(*u, *v, p) / 3.)
+            .sum()
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        let weights = self.weights(&rec.normal);
+        let mut projected = rec.clone();
+        // The new coordinates run scale times per unit of distance in every direction
+        let width = if rec.uv_per_unit > 0. {
+            rec.footprint / rec.uv_per_unit
+        } else {
+            0.
+        };
+        projected.uv_per_unit = self.scale;
+        projected.set_footprint(width);
+
+        let mut sum = Color::default();
+        for (weight, (u, v)) in weights.iter().zip(self.projections(&rec.p)) {
+            if *weight > 0. {
+                (projected.u, projected.v) = (u, v);
+                sum += self.texture.sample(&projected) * *weight;
+            }
+        }
+        sum
+    }
+}
+
+// Maps the first channel of a scalar texture from [low, high] onto the colors from to to,
+// clamping outside the range. For example turns a height map into a tint, or inverts a mask
+// with from white to black
+#[derive(Debug)]
+pub struct Remap {
+    input: Arc<dyn Texture>,
+    low: f64,
+    high: f64,
+    from: Color,
+    to: Color,
+}
+
+impl Remap {
+    pub fn new(input: Arc<dyn Texture>, low: f64, high: f64, from: Color, to: Color) -> Self {
+        Self {
+            input,
+            low,
+            high,
+            from,
+            to,
+        }
+    }
+
+    fn remap(&self, value: Color) -> Color {
+        let t = if self.high != self.low {
+            ((value.x() - self.low) / (self.high - self.low)).clamp(0., 1.)
+        } else if value.x() < self.low {
+            0.
+        } else {
+            1.
+        };
+        self.from + (self.to - self.from) * t
+    }
+}
+
+impl Texture for Remap {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.remap(self.input.value(u, v, p))
+    }
+
+    fn alpha(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        self.input.alpha(u, v, p)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.remap(self.input.sample(rec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        alpha_mask::AlphaMasked, hittable::Hittable, interval::Interval, material::Lambertian,
+        quad::Quad, ray::Ray,
+    };
+
+    // Texture that shows the coordinates it is read at
+    #[derive(Debug)]
+    struct Coordinates;
+    impl Texture for Coordinates {
+        fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+            Color::new(u, v, 0.)
+        }
+    }
+
+    // White, cut out on the right half of the uv square
+    #[derive(Debug)]
+    struct HalfMask;
+    impl Texture for HalfMask {
+        fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+            Color::new(1., 1., 1.)
+        }
+
+        fn alpha(&self, u: f64, _v: f64, _p: &Point3) -> f64 {
+            if u < 0.5 {
+                1.
+            } else {
+                0.
+            }
+        }
+    }
+
+    fn solid(r: f64, g: f64, b: f64) -> Arc<dyn Texture> {
+        Arc::new(SolidColor::with_rgb(r, g, b))
+    }
+
+    fn near(a: Color, b: Color) -> bool {
+        (a - b).near_zero()
+    }
+
+    #[test]
+    fn mix_and_combine() {
+        let p = Point3::default();
+        let mix = Mix::with_factor(solid(1., 0., 0.), solid(0., 0., 1.), 0.25);
+        assert!(near(mix.value(0., 0., &p), Color::new(0.75, 0., 0.25)));
+        let product = Combine::multiply(solid(0.5, 1., 1.), solid(0.5, 0.5, 0.));
+        assert!(near(product.value(0., 0., &p), Color::new(0.25, 0.5, 0.)));
+        let sum = Combine::add(solid(0.5, 1., 1.), solid(0.5, 0.5, 0.));
+        assert!(near(sum.value(0., 0., &p), Color::new(1., 1.5, 1.)));
+    }
+
+    #[test]
+    fn masks_show_through_mix() {
+        let mix: Arc<dyn Texture> =
+            Arc::new(Mix::with_factor(Arc::new(HalfMask), solid(0., 0., 0.), 0.));
+        let p = Point3::default();
+        assert_eq!(mix.alpha(0.25, 0.5, &p), 1.);
+        assert_eq!(mix.alpha(0.75, 0.5, &p), 0.);
+
+        // Rays go through the cut out half of a quad masked by the mix
+        let quad = Arc::new(Quad::new(
+            Point3::default(),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ));
+        let masked = AlphaMasked::new(quad, mix);
+        let hits = |x: f64| {
+            let r = Ray::new(Point3::new(x, 0.5, 1.), Vec3::new(0., 0., -1.));
+            let mut rec = HitRecord::default();
+            masked.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec)
+        };
+        assert!(hits(0.25));
+        assert!(!hits(0.75));
+    }
+
+    #[test]
+    fn uv_transform_scales_rotates_and_offsets() {
+        let p = Point3::default();
+        let rotated = UvTransform::new(Arc::new(Coordinates), [2., 2.], 90., [0.5, 0.]);
+        // (1, 0) scaled to (2, 0), turned to (0, 2) and moved to (0.5, 2)
+        assert!(near(rotated.value(1., 0., &p), Color::new(0.5, 2., 0.)));
+
+        let rec = HitRecord {
+            u: 0.25,
+            v: 0.5,
+            ..Default::default()
+        };
+        let tiled = UvTransform::scale(Arc::new(Coordinates), 4., 4.);
+        assert!(near(tiled.sample(&rec), Color::new(1., 2., 0.)));
+    }
+
+    #[test]
+    fn triplanar_follows_the_normal() {
+        let triplanar = Triplanar::new(Arc::new(Coordinates), 1.);
+        let rec = HitRecord {
+            p: Point3::new(0.1, 0.2, 0.3),
+            normal: Vec3::new(0., 1., 0.),
+            ..Default::default()
+        };
+        // Facing up the texture is laid out over x and z
+        assert!(near(triplanar.sample(&rec), Color::new(0.1, 0.3, 0.)));
+        // Without a normal all three projections are averaged
+        let even = triplanar.value(0., 0., &rec.p);
+        assert!(near(even, Color::new(0.5 / 3., 0.7 / 3., 0.)));
+    }
+
+    #[test]
+    fn remap_clamps_to_range() {
+        let p = Point3::default();
+        let remap = |x: f64| {
+            Remap::new(
+                solid(x, x, x),
+                0.2,
+                0.6,
+                Color::new(1., 1., 1.),
+                Color::default(),
+            )
+            .value(0., 0., &p)
+        };
+        assert!(near(remap(0.), Color::new(1., 1., 1.)));
+        assert!(near(remap(0.4), Color::new(0.5, 0.5, 0.5)));
+        assert!(near(remap(1.), Color::default()));
+    }
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    hittable::{hit_segment_end, HitRecord, Hittable},
+    interval::Interval,
+    matrix::Mat4,
+    ray::{Point3, Ray},
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+
+/// Places a hittable in the world with an affine transform.
+/// Rays are moved into the object's local space, and hits are moved back into world space. The
+/// inner object is shared, so one mesh can be instanced many times with different transforms.
+#[derive(Debug)]
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    matrix: Mat4,        // object space to world space
+    inverse: Mat4,       // world space to object space
+    normal_matrix: Mat4, // inverse transpose, maps object space normals to world space
+    determinant: f64,    // of the linear part, how much the transform scales volumes
+    bbox: AABB,
+}
+
+impl Transform {
+    // Panics if the matrix is not invertible, e.g. a scale of zero along some axis
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Self {
+        let inverse = matrix
+            .inverse()
+            .expect("transform matrix must be invertible");
+
+        // The world space box is the box around all eight transformed corners of the local box
+        let local = object.bounding_box();
+        let mut bbox = AABB::empty();
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 {
+                    local.axis_interval(0).min
+                } else {
+                    local.axis_interval(0).max
+                },
+                if i & 2 == 0 {
+                    local.axis_interval(1).min
+                } else {
+                    local.axis_interval(1).max
+                },
+                if i & 4 == 0 {
+                    local.axis_interval(2).min
+                } else {
+                    local.axis_interval(2).max
+                },
+            );
+            let p = matrix.transform_point(&corner);
+            bbox = AABB::with_boxes(&bbox, &AABB::with_points(&p, &p));
+        }
+
+        let determinant = dot(
+            matrix.transform_vector(&Vec3::new(1., 0., 0.)),
+            cross(
+                matrix.transform_vector(&Vec3::new(0., 1., 0.)),
+                matrix.transform_vector(&Vec3::new(0., 0., 1.)),
+            ),
+        );
+
+        Self {
+            object,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+            determinant,
+            bbox: bbox.pad_to_minimums(),
+        }
+    }
+
+    pub fn translate(object: Arc<dyn Hittable>, offset: Vec3) -> Self {
+        Self::new(object, Mat4::translation(offset))
+    }
+
+    // Rotation in degrees around the Y-axis through the object's local origin
+    pub fn rotate_y(object: Arc<dyn Hittable>, degrees: f64) -> Self {
+        Self::new(object, Mat4::rotation_y(degrees))
+    }
+
+    pub fn scale(object: Arc<dyn Hittable>, factors: Vec3) -> Self {
+        Self::new(object, Mat4::scaling(factors))
+    }
+
+    // How much the transform scales a small patch of surface with the given object space unit
+    // normal, |det M| * |M^-T n| by Nanson's formula
+    fn area_stretch(&self, normal: &Vec3) -> f64 {
+        f64::abs(self.determinant) * self.normal_matrix.transform_vector(normal).length()
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // The direction is not normalized after the transform, so the ray parameter t is the
+        // same in both spaces and ray_t can be passed through as is
+        let local_ray = Ray::new_tm(
+            self.inverse.transform_point(&r.origin()),
+            self.inverse.transform_vector(&r.direction()),
+            r.time(),
+        );
+        if !self.object.hit(&local_ray, ray_t, rec) {
+            return false;
+        }
+
+        // The inverse transpose keeps normals perpendicular to the surface under non-uniform
+        // scaling, and front_face stays valid since dot(d, n) is preserved
+        rec.p = self.matrix.transform_point(&rec.p);
+        rec.normal = unit_vector(&self.normal_matrix.transform_vector(&rec.normal));
+        if !rec.tangent.near_zero() {
+            let tangent = self.matrix.transform_vector(&rec.tangent);
+            let bitangent = self.matrix.transform_vector(&rec.bitangent);
+            // The frame is unit length, so the new uv_per_unit only has the stretch of the
+            // transform and the object space one has to be carried over
+            let uv_per_unit = rec.uv_per_unit;
+            rec.set_tangent_frame(&tangent, &bitangent);
+            rec.uv_per_unit *= uv_per_unit;
+        }
+        true
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let local_direction = unit_vector(&self.inverse.transform_vector(direction));
+        let local_pdf = self
+            .object
+            .pdf_value(&self.inverse.transform_point(origin), &local_direction);
+        // Directions are squeezed or spread out by the transform unless it is a rotation. For a
+        // linear map M and unit vector d, solid angles grow by |det M| / |M d|^3
+        let stretch = self.matrix.transform_vector(&local_direction).length();
+        local_pdf * stretch.powi(3) / f64::abs(self.determinant)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let local_origin = self.inverse.transform_point(origin);
+        self.matrix
+            .transform_vector(&self.object.random(&local_origin))
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        let (mut rec, local_pdf) = self.object.sample_surface()?;
+        let stretch = self.area_stretch(&rec.normal);
+        rec.p = self.matrix.transform_point(&rec.p);
+        rec.normal = unit_vector(&self.normal_matrix.transform_vector(&rec.normal));
+        Some((rec, local_pdf / stretch))
+    }
+
+    fn surface_pdf(&self, origin: &Point3, point: &Point3) -> f64 {
+        let local_origin = self.inverse.transform_point(origin);
+        let local_point = self.inverse.transform_point(point);
+        let mut rec = HitRecord::default();
+        if !hit_segment_end(self.object.as_ref(), &local_origin, &local_point, &mut rec) {
+            return 0.;
+        }
+        self.object.surface_pdf(&local_origin, &local_point) / self.area_stretch(&rec.normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian, quad::make_box, sphere::Sphere};
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(
+            Point3::new(0., 0., 0.),
+            1.,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    #[test]
+    fn translated_instances_share_object() {
+        let sphere = unit_sphere();
+        let a = Transform::translate(sphere.clone(), Vec3::new(5., 0., 0.));
+        let b = Transform::translate(sphere, Vec3::new(-5., 0., 0.));
+
+        let r = Ray::new(Point3::new(5., 0., 10.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(a.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!((rec.p - Point3::new(5., 0., 1.)).near_zero());
+        assert_eq!(rec.t, 9.);
+        assert!(!b.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+    }
+
+    #[test]
+    fn scaled_normals_stay_perpendicular() {
+        // An ellipsoid stretched along x, hit where its local space normal is (1, 1, 1)
+        let ellipsoid = Transform::scale(unit_sphere(), Vec3::new(2., 1., 1.));
+        let local = unit_vector(&Vec3::new(1., 1., 1.));
+        let target = Point3::new(2. * local.x(), local.y(), local.z());
+        let r = Ray::new(target + Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(ellipsoid.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!((rec.p - target).length() < 1e-9);
+        // The gradient of x^2/4 + y^2 + z^2 at the hit point
+        let expected = unit_vector(&Vec3::new(target.x() / 4., target.y(), target.z()));
+        assert!((rec.normal - expected).length() < 1e-9);
+    }
+
+    #[test]
+    fn rotated_bbox_contains_corners() {
+        let cube = make_box(
+            &Point3::new(0., 0., 0.),
+            &Point3::new(1., 1., 1.),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let rotated = Transform::rotate_y(cube, 45.);
+        let bbox = rotated.bounding_box();
+        let half_diagonal = f64::sqrt(2.);
+        assert!(bbox.axis_interval(0).size() >= half_diagonal - 1e-6);
+        assert!(bbox.axis_interval(2).size() >= half_diagonal - 1e-6);
+        assert!(bbox.axis_interval(1).contains(1.));
+    }
+}
@@ -0,0 +1,318 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    hittable::{hit_segment_end, HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::{Point3, Ray},
+    utils::random_double,
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+
+/// Möller–Trumbore ray/triangle intersection.
+/// Returns the ray parameter t and the barycentric coordinates (b1, b2) of the hit point,
+/// where the point is (1 - b1 - b2) * v0 + b1 * v1 + b2 * v2.
+pub fn intersect(
+    v0: &Point3,
+    v1: &Point3,
+    v2: &Point3,
+    r: &Ray,
+    ray_t: Interval,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = *v1 - *v0;
+    let edge2 = *v2 - *v0;
+    let pvec = cross(r.direction(), edge2);
+    let det = dot(edge1, pvec);
+
+    // The ray is parallel to the triangle plane
+    if f64::abs(det) < 1e-12 {
+        return None;
+    }
+    let inv_det = 1. / det;
+
+    let tvec = r.origin() - *v0;
+    let b1 = dot(tvec, pvec) * inv_det;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+
+    let qvec = cross(tvec, edge1);
+    let b2 = dot(r.direction(), qvec) * inv_det;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+
+    let t = dot(edge2, qvec) * inv_det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Fill the hit record for a triangle hit given its barycentric coordinates.
+/// Without per-vertex UVs the barycentrics themselves are used as (u, v). Per-vertex normals
+/// are interpolated for shading, while front_face is still decided by the geometric normal.
+pub fn set_hit_record(
+    vertices: [&Point3; 3],
+    normals: Option<[&Vec3; 3]>,
+    uvs: Option<[&[f64; 2]; 3]>,
+    hit: (f64, f64, f64),
+    r: &Ray,
+    rec: &mut HitRecord,
+) {
+    let (t, b1, b2) = hit;
+    let b0 = 1. - b1 - b2;
+    rec.t = t;
+    rec.p = r.at(t);
+
+    let geometric_normal = unit_vector(&cross(
+        *vertices[1] - *vertices[0],
+        *vertices[2] - *vertices[0],
+    ));
+    rec.set_face_normal(r, &geometric_normal);
+    if let Some([n0, n1, n2]) = normals {
+        let shading_normal = unit_vector(&(*n0 * b0 + *n1 * b1 + *n2 * b2));
+        rec.normal = if rec.front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+    }
+
+    let edge1 = *vertices[1] - *vertices[0];
+    let edge2 = *vertices[2] - *vertices[0];
+    let (dpdu, dpdv) = match uvs {
+        Some([uv0, uv1, uv2]) => {
+            rec.u = uv0[0] * b0 + uv1[0] * b1 + uv2[0] * b2;
+            rec.v = uv0[1] * b0 + uv1[1] * b1 + uv2[1] * b2;
+            // Solve edge = dpdu * du + dpdv * dv for both edges
+            let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+            let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+            let det = du1 * dv2 - dv1 * du2;
+            if f64::abs(det) < 1e-12 {
+                (edge1, edge2)
+            } else {
+                (
+                    (edge1 * dv2 - edge2 * dv1) / det,
+                    (edge2 * du1 - edge1 * du2) / det,
+                )
+            }
+        }
+        None => {
+            rec.u = b1;
+            rec.v = b2;
+            (edge1, edge2)
+        }
+    };
+    rec.set_tangent_frame(&dpdu, &dpdv);
+}
+
+#[derive(Debug)]
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[[f64; 2]; 3]>,
+    material: Option<Arc<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: Arc<dyn Material>) -> Self {
+        Self::with_attributes([v0, v1, v2], None, None, material)
+    }
+
+    pub fn with_attributes(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[[f64; 2]; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let bbox = AABB::with_boxes(
+            &AABB::with_points(&vertices[0], &vertices[1]),
+            &AABB::with_points(&vertices[2], &vertices[2]),
+        )
+        .pad_to_minimums();
+        Self {
+            vertices,
+            normals,
+            uvs,
+            material: Some(material),
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let [v0, v1, v2] = &self.vertices;
+        match intersect(v0, v1, v2, r, ray_t) {
+            Some(hit) => {
+                set_hit_record(
+                    [v0, v1, v2],
+                    self.normals.as_ref().map(|[n0, n1, n2]| [n0, n1, n2]),
+                    self.uvs.as_ref().map(|[uv0, uv1, uv2]| [uv0, uv1, uv2]),
+                    hit,
+                    r,
+                    rec,
+                );
+                rec.material = self.material.clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let [v0, v1, v2] = &self.vertices;
+        let Some((t, _, _)) = intersect(
+            v0,
+            v1,
+            v2,
+            &Ray::new(*origin, *direction),
+            Interval::new(0.001, f64::INFINITY),
+        ) else {
+            return 0.;
+        };
+        // Same conversion from area to solid angle density as for quads
+        let n = cross(*v1 - *v0, *v2 - *v0);
+        let area = 0.5 * n.length();
+        let distance_squared = t * t * direction.length_squared();
+        let cosine = f64::abs(dot(*direction, n) / (direction.length() * n.length()));
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        // Fold the unit square onto the triangle so points are spread uniformly over its area
+        let (mut b1, mut b2) = (random_double(), random_double());
+        if b1 + b2 > 1. {
+            (b1, b2) = (1. - b1, 1. - b2);
+        }
+        let [v0, v1, v2] = self.vertices;
+        let p = v0 + (v1 - v0) * b1 + (v2 - v0) * b2;
+        p - *origin
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f64)> {
+        let [v0, v1, v2] = self.vertices;
+        let n = cross(v1 - v0, v2 - v0);
+        let (mut b1, mut b2) = (random_double(), random_double());
+        if b1 + b2 > 1. {
+            (b1, b2) = (1. - b1, 1. - b2);
+        }
+        // Go through the hit record setup so uvs are interpolated like for a hit, with a ray
+        // arriving from the front. Light paths use the geometric normal
+        let p = v0 + (v1 - v0) * b1 + (v2 - v0) * b2;
+        let r = Ray::new(p + n, -n);
+        let mut rec = HitRecord::default();
+        set_hit_record(
+            [&v0, &v1, &v2],
+            self.normals.as_ref().map(|[n0, n1, n2]| [n0, n1, n2]),
+            self.uvs.as_ref().map(|[uv0, uv1, uv2]| [uv0, uv1, uv2]),
+            (1., b1, b2),
+            &r,
+            &mut rec,
+        );
+        rec.normal = unit_vector(&n);
+        rec.material = self.material.clone();
+        Some((rec, 2. / n.length()))
+    }
+
+    fn surface_pdf(&self, origin: &Point3, point: &Point3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !hit_segment_end(self, origin, point, &mut rec) {
+            return 0.;
+        }
+        let [v0, v1, v2] = self.vertices;
+        2. / cross(v1 - v0, v2 - v0).length()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn tangent_frame_follows_uvs() {
+        // u runs along -y and v along +x, mirrored from the vertex order
+        let triangle = Triangle::with_attributes(
+            [
+                Point3::new(0., 0., 0.),
+                Point3::new(1., 0., 0.),
+                Point3::new(0., 1., 0.),
+            ],
+            None,
+            Some([[1., 0.], [1., 1.], [0., 0.]]),
+            material(),
+        );
+        let r = Ray::new(Point3::new(0.2, 0.2, 1.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(triangle.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!((rec.tangent - Vec3::new(0., -1., 0.)).near_zero());
+        assert!((rec.bitangent - Vec3::new(1., 0., 0.)).near_zero());
+    }
+
+    #[test]
+    fn hit_barycentric_uv() {
+        let tri = Triangle::new(
+            Point3::new(0., 0., 0.),
+            Point3::new(1., 0., 0.),
+            Point3::new(0., 1., 0.),
+            material(),
+        );
+        let r = Ray::new(Point3::new(0.25, 0.5, 2.), Vec3::new(0., 0., -1.));
+        let mut rec = HitRecord::default();
+        assert!(tri.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert_eq!(rec.t, 2.);
+        assert_eq!(rec.u, 0.25);
+        assert_eq!(rec.v, 0.5);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn miss_outside_and_behind() {
+        let tri = Triangle::new(
+            Point3::new(0., 0., 0.),
+            Point3::new(1., 0., 0.),
+            Point3::new(0., 1., 0.),
+            material(),
+        );
+        let mut rec = HitRecord::default();
+        let outside = Ray::new(Point3::new(0.75, 0.75, 2.), Vec3::new(0., 0., -1.));
+        assert!(!tri.hit(&outside, Interval::new(0.001, f64::INFINITY), &mut rec));
+        let behind = Ray::new(Point3::new(0.25, 0.25, 2.), Vec3::new(0., 0., 1.));
+        assert!(!tri.hit(&behind, Interval::new(0.001, f64::INFINITY), &mut rec));
+    }
+
+    #[test]
+    fn vertex_attributes_are_interpolated() {
+        let n = unit_vector(&Vec3::new(1., 0., 1.));
+        let tri = Triangle::with_attributes(
+            [
+                Point3::new(0., 0., 0.),
+                Point3::new(1., 0., 0.),
+                Point3::new(0., 1., 0.),
+            ],
+            Some([n, n, n]),
+            Some([[0., 0.], [1., 0.], [1., 1.]]),
+            material(),
+        );
+        // Hit from the back: the shading normal must be flipped along with the geometric one
+        let r = Ray::new(Point3::new(0.5, 0.25, -2.), Vec3::new(0., 0., 1.));
+        let mut rec = HitRecord::default();
+        assert!(tri.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!(!rec.front_face);
+        assert!((rec.normal - -n).near_zero());
+        assert_eq!(rec.u, 0.75);
+        assert_eq!(rec.v, 0.25);
+    }
+}
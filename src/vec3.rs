@@ -52,6 +52,17 @@ impl Vec3 {
             }
         }
     }
+    // Random direction around +Z, distributed as cos(theta) / pi
+    pub fn random_cosine_direction() -> Self {
+        let r1 = random_double();
+        let r2 = random_double();
+
+        let phi = 2. * std::f64::consts::PI * r1;
+        let x = f64::cos(phi) * f64::sqrt(r2);
+        let y = f64::sin(phi) * f64::sqrt(r2);
+        let z = f64::sqrt(1. - r2);
+        Self::new(x, y, z)
+    }
     pub fn random_on_hemisphere(normal: &Self) -> Vec3 {
         let on_unit_sphere = Self::random_unit_vector();
         if dot(on_unit_sphere, *normal) > 0. {